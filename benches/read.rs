@@ -0,0 +1,73 @@
+//! Measures the reply path of `device_read` for large transfers.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::executor::block_on;
+use onc_rpc::auth::AuthFlavor;
+use onc_rpc::{AcceptedReply, AcceptedStatus, MessageType, ReplyBody, RpcMessage};
+
+use async_vxi11::rpc::Request;
+use async_vxi11::{Client, CoreClient, ReadOptions};
+
+const CALL_CREATE_LINK: u32 = 10;
+
+/// An instrument answering every read with the same prebuilt reply.
+#[derive(Clone)]
+struct Waveform {
+    create_link: Bytes,
+    read: Bytes,
+}
+
+fn reply(payload: &[u8]) -> Bytes {
+    let reply = AcceptedReply::new(AuthFlavor::AuthNone(None), AcceptedStatus::Success(payload));
+    let msg = RpcMessage::<&[u8], &[u8]>::new(1, MessageType::Reply(ReplyBody::Accepted(reply)));
+    Bytes::from(msg.serialise().unwrap())
+}
+
+impl Waveform {
+    fn new(size: usize) -> Self {
+        let mut create_link = Vec::new();
+        for x in &[0_u32, 1, 1234, size as u32] {
+            create_link.extend_from_slice(&x.to_be_bytes());
+        }
+        let mut read = Vec::with_capacity(size + 12);
+        for x in &[0_u32, 4, size as u32] {
+            read.extend_from_slice(&x.to_be_bytes());
+        }
+        read.resize(size + 12, 0x55);
+        Self {
+            create_link: reply(&create_link),
+            read: reply(&read),
+        }
+    }
+}
+
+#[async_trait]
+impl Client for Waveform {
+    async fn call(&self, body: Request) -> async_vxi11::Result<Bytes> {
+        if body.procedure() == CALL_CREATE_LINK {
+            Ok(self.create_link.clone())
+        } else {
+            Ok(self.read.clone())
+        }
+    }
+}
+
+fn device_read(c: &mut Criterion) {
+    let mut group = c.benchmark_group("device_read");
+    for size in [64 * 1024, 1024 * 1024] {
+        let mut client = block_on(CoreClient::from_client(Waveform::new(size), "inst0")).unwrap();
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("vec", size), &size, |b, _| {
+            b.iter(|| block_on(client.device_read()).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("bytes", size), &size, |b, _| {
+            b.iter(|| block_on(client.device_read_bytes(ReadOptions::default())).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, device_read);
+criterion_main!(benches);
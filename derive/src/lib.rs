@@ -0,0 +1,226 @@
+//! Derive macros for the XDR traits [`Serialize`] and [`Deserialize`] of `async-vxi11`.
+//!
+//! Structs are encoded as the sequence of their fields. Enums are encoded as XDR
+//! discriminated unions: the discriminant, given with `#[xdr(case = N)]` or defaulting to
+//! the index of the variant, followed by the fields of the variant. Enums without any
+//! fields thus correspond to XDR enums.
+//!
+//! The types of all fields must implement the traits themselves. For generic types, the
+//! type parameters are required to implement the derived trait.
+//!
+//! [`Serialize`]: https://docs.rs/async-vxi11/latest/async_vxi11/xdr/trait.Serialize.html
+//! [`Deserialize`]: https://docs.rs/async-vxi11/latest/async_vxi11/xdr/trait.Deserialize.html
+
+use proc_macro::TokenStream;
+use proc_macro2::{Ident, Literal, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Data, DataEnum, DeriveInput, Error, Fields, Generics, Variant,
+};
+
+#[proc_macro_derive(XdrSerialize, attributes(xdr))]
+pub fn derive_serialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_serialize(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(XdrDeserialize, attributes(xdr))]
+pub fn derive_deserialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_deserialize(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand_serialize(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let (pattern, names) = bindings(&data.fields);
+            let fields = serialize_fields(&names);
+            quote! {
+                let Self #pattern = self;
+                #(#fields)*
+            }
+        }
+        Data::Enum(data) => {
+            let arms = cases(data)?.into_iter().map(|(case, variant)| {
+                let ident = &variant.ident;
+                let (pattern, names) = bindings(&variant.fields);
+                let fields = serialize_fields(&names);
+                quote! {
+                    Self::#ident #pattern => {
+                        ::async_vxi11::xdr::Serialize::serialize(&#case, __out);
+                        #(#fields)*
+                    }
+                }
+            });
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => return Err(Error::new_spanned(input, "unions are not supported")),
+    };
+    let name = &input.ident;
+    let generics = bounded(&input.generics, quote!(::async_vxi11::xdr::Serialize));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::async_vxi11::xdr::Serialize for #name #ty_generics #where_clause {
+            fn serialize(&self, __out: &mut ::std::vec::Vec<u8>) {
+                #body
+            }
+        }
+    })
+}
+
+fn expand_deserialize(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let (pattern, names) = bindings(&data.fields);
+            let fields = deserialize_fields(&data.fields, &names);
+            quote! {
+                #(#fields)*
+                Self #pattern
+            }
+        }
+        Data::Enum(data) => {
+            let arms = cases(data)?.into_iter().map(|(case, variant)| {
+                let ident = &variant.ident;
+                let (pattern, names) = bindings(&variant.fields);
+                let fields = deserialize_fields(&variant.fields, &names);
+                quote! {
+                    #case => {
+                        #(#fields)*
+                        Self::#ident #pattern
+                    }
+                }
+            });
+            quote! {
+                let __offset = __decoder.offset();
+                let __case = __decoder.i32()?;
+                match __case {
+                    #(#arms)*
+                    _ => {
+                        let kind = ::async_vxi11::xdr::DecodeErrorKind::InvalidDiscriminant(__case);
+                        return ::std::result::Result::Err(__decoder.error(__offset, kind));
+                    }
+                }
+            }
+        }
+        Data::Union(_) => return Err(Error::new_spanned(input, "unions are not supported")),
+    };
+    let min_size = match &input.data {
+        Data::Struct(data) => {
+            let sizes = data.fields.iter().map(|field| {
+                let ty = &field.ty;
+                quote!(<#ty as ::async_vxi11::xdr::Deserialize>::MIN_SIZE)
+            });
+            quote!(0 #(+ #sizes)*)
+        }
+        _ => quote!(4),
+    };
+    let name = &input.ident;
+    let generics = bounded(&input.generics, quote!(::async_vxi11::xdr::Deserialize));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::async_vxi11::xdr::Deserialize for #name #ty_generics #where_clause {
+            const MIN_SIZE: usize = #min_size;
+
+            fn decode(
+                __decoder: &mut ::async_vxi11::xdr::Decoder<'_>,
+            ) -> ::async_vxi11::Result<Self> {
+                ::std::result::Result::Ok({
+                    #body
+                })
+            }
+        }
+    })
+}
+
+/// `generics` with `bound` added to every type parameter.
+fn bounded(generics: &Generics, bound: TokenStream2) -> Generics {
+    let mut generics = generics.clone();
+    let params: Vec<Ident> = generics.type_params().map(|x| x.ident.clone()).collect();
+    let where_clause = generics.make_where_clause();
+    for param in params {
+        where_clause.predicates.push(parse_quote!(#param: #bound));
+    }
+    generics
+}
+
+/// The pattern destructuring `fields` and the names of the bindings.
+fn bindings(fields: &Fields) -> (TokenStream2, Vec<Ident>) {
+    match fields {
+        Fields::Named(fields) => {
+            let names: Vec<_> = fields
+                .named
+                .iter()
+                .map(|x| x.ident.clone().unwrap())
+                .collect();
+            (quote!({ #(#names),* }), names)
+        }
+        Fields::Unnamed(fields) => {
+            let names: Vec<_> = (0..fields.unnamed.len())
+                .map(|i| format_ident!("field{}", i))
+                .collect();
+            (quote!(( #(#names),* )), names)
+        }
+        Fields::Unit => (quote!(), Vec::new()),
+    }
+}
+
+fn serialize_fields(names: &[Ident]) -> Vec<TokenStream2> {
+    names
+        .iter()
+        .map(|name| {
+            quote! {
+                ::async_vxi11::xdr::Serialize::serialize(#name, __out);
+            }
+        })
+        .collect()
+}
+
+fn deserialize_fields(fields: &Fields, names: &[Ident]) -> Vec<TokenStream2> {
+    fields
+        .iter()
+        .zip(names)
+        .map(|(field, name)| {
+            let ty = &field.ty;
+            quote! {
+                let #name: #ty = __decoder.decode()?;
+            }
+        })
+        .collect()
+}
+
+/// The discriminants of the variants of an enum, as `i32` literals.
+fn cases(data: &DataEnum) -> syn::Result<Vec<(Literal, &Variant)>> {
+    let mut ret: Vec<(i32, &Variant)> = Vec::new();
+    for (index, variant) in data.variants.iter().enumerate() {
+        let mut case = index as i32;
+        for attr in variant.attrs.iter().filter(|x| x.path().is_ident("xdr")) {
+            attr.parse_nested_meta(|meta| {
+                if !meta.path.is_ident("case") {
+                    return Err(meta.error("expected `case = N`"));
+                }
+                let value = meta.value()?;
+                let negative = value.parse::<Option<syn::Token![-]>>()?.is_some();
+                let lit: syn::LitInt = value.parse()?;
+                let value: i32 = lit.base10_parse()?;
+                case = if negative { -value } else { value };
+                Ok(())
+            })?;
+        }
+        if ret.iter().any(|(x, _)| *x == case) {
+            return Err(Error::new_spanned(variant, "duplicate case"));
+        }
+        ret.push((case, variant));
+    }
+    Ok(ret
+        .into_iter()
+        .map(|(case, variant)| (Literal::i32_suffixed(case), variant))
+        .collect())
+}
@@ -0,0 +1,231 @@
+//! Authentication of RPC calls.
+//!
+//! By default, calls are sent with `AUTH_NONE`. Servers requiring `AUTH_SYS` (also known
+//! as `AUTH_UNIX`) are accessed by configuring [`Credentials::Sys`], e.g. with
+//! [`crate::Connection::with_credentials()`]. If the server hands out an `AUTH_SHORT`
+//! verifier, it is used as shorthand credentials for the following calls until the
+//! server rejects it.
+
+use std::sync::Mutex;
+
+use onc_rpc::auth::{AuthFlavor, AuthUnixParams};
+use onc_rpc::CallBody;
+use smallvec::SmallVec;
+
+use crate::rpc::{AuthStat, Request};
+use crate::Error;
+
+/// Credentials sent with every call.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Credentials {
+    /// `AUTH_NONE`
+    #[default]
+    None,
+    /// `AUTH_SYS` credentials identifying the user on the client machine.
+    Sys {
+        stamp: u32,
+        machine_name: String,
+        uid: u32,
+        gid: u32,
+        gids: Vec<u32>,
+    },
+}
+
+impl Credentials {
+    /// `AUTH_SYS` credentials for `machine_name` with the given user and group id.
+    pub fn sys<S: Into<String>>(machine_name: S, uid: u32, gid: u32) -> Self {
+        Credentials::Sys {
+            stamp: 0,
+            machine_name: machine_name.into(),
+            uid,
+            gid,
+            gids: Vec::new(),
+        }
+    }
+}
+
+/// The credentials of a connection together with the `AUTH_SHORT` handle assigned by
+/// the server.
+#[derive(Debug, Default)]
+pub struct Auth {
+    credentials: Credentials,
+    short: Mutex<Option<Vec<u8>>>,
+}
+
+impl Auth {
+    pub fn new(credentials: Credentials) -> Self {
+        Self {
+            credentials,
+            short: Mutex::new(None),
+        }
+    }
+
+    pub fn credentials(&self) -> &Credentials {
+        &self.credentials
+    }
+
+    /// Returns true if calls are sent with the `AUTH_SHORT` handle of the server.
+    pub fn uses_short(&self) -> bool {
+        self.short.lock().unwrap().is_some()
+    }
+
+    /// Forget the `AUTH_SHORT` handle, e.g. after the server rejected it, such that the
+    /// full credentials are sent again.
+    pub fn reset_short(&self) {
+        *self.short.lock().unwrap() = None;
+    }
+
+    /// Build a request with the current credentials.
+    pub fn request(&self, prog: u32, vers: u32, procedure: u32, payload: Vec<u8>) -> Request {
+        CallBody::new(
+            prog,
+            vers,
+            procedure,
+            self.flavor(),
+            AuthFlavor::AuthNone(None),
+            payload,
+        )
+    }
+
+    fn flavor(&self) -> AuthFlavor<Vec<u8>> {
+        if let Some(short) = self.short.lock().unwrap().as_ref() {
+            return AuthFlavor::AuthShort(short.clone());
+        }
+        match &self.credentials {
+            Credentials::None => AuthFlavor::AuthNone(None),
+            Credentials::Sys {
+                stamp,
+                machine_name,
+                uid,
+                gid,
+                gids,
+            } => AuthFlavor::AuthUnix(AuthUnixParams::new(
+                *stamp,
+                machine_name.as_bytes().to_vec(),
+                *uid,
+                *gid,
+                Some(SmallVec::from_slice(gids)),
+            )),
+        }
+    }
+
+    /// Check the verifier of a reply. An `AUTH_SHORT` verifier is remembered and used as
+    /// credentials for the following calls.
+    pub fn verify(&self, verifier: &AuthFlavor<&[u8]>) -> crate::Result<()> {
+        match (verifier, &self.credentials) {
+            (AuthFlavor::AuthNone(_), _) => Ok(()),
+            (AuthFlavor::AuthShort(short), Credentials::Sys { .. }) => {
+                *self.short.lock().unwrap() = Some(short.to_vec());
+                Ok(())
+            }
+            _ => Err(Error::RpcAuthError(AuthStat::InvalidResponseVerifier)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use onc_rpc::{
+        AcceptedReply, AcceptedStatus, AuthError, MessageType, RejectedReply, ReplyBody, RpcMessage,
+    };
+
+    use super::*;
+    use crate::rpc::{call_with_auth, Client};
+
+    /// A server handing out an `AUTH_SHORT` handle and forgetting it after one call.
+    #[derive(Clone, Default)]
+    struct ShortServer(Arc<Mutex<Vec<&'static str>>>);
+
+    #[async_trait]
+    impl Client for ShortServer {
+        async fn call(&self, body: Request) -> crate::Result<Bytes> {
+            let mut calls = self.0.lock().unwrap();
+            let reply = match body.auth_credentials() {
+                AuthFlavor::AuthUnix(params) => {
+                    assert_eq!(params.machine_name_str(), "bench");
+                    calls.push("sys");
+                    let verifier = AuthFlavor::AuthShort(&b"1234"[..]);
+                    let status = AcceptedStatus::Success(&[0_u8, 0, 0, 7][..]);
+                    ReplyBody::Accepted(AcceptedReply::new(verifier, status))
+                }
+                AuthFlavor::AuthShort(short) if calls.len() == 1 => {
+                    assert_eq!(short, b"1234");
+                    calls.push("short");
+                    let status = AcceptedStatus::Success(&[0_u8, 0, 0, 8][..]);
+                    ReplyBody::Accepted(AcceptedReply::new(AuthFlavor::AuthNone(None), status))
+                }
+                _ => {
+                    calls.push("rejected");
+                    ReplyBody::Denied(RejectedReply::AuthError(AuthError::RejectedCredentials))
+                }
+            };
+            let msg = RpcMessage::<&[u8], &[u8]>::new(1, MessageType::Reply(reply));
+            Ok(Bytes::from(msg.serialise().unwrap()))
+        }
+    }
+
+    #[tokio::test]
+    async fn auth_short_handle() {
+        let server = ShortServer::default();
+        let auth = Auth::new(Credentials::sys("bench", 1000, 100));
+
+        let first: u32 = call_with_auth(&server, &auth, &0_u32, 1, 1, 1)
+            .await
+            .unwrap();
+        assert!(auth.uses_short());
+        let second: u32 = call_with_auth(&server, &auth, &0_u32, 1, 1, 1)
+            .await
+            .unwrap();
+        // the server forgot the handle, the call is repeated with the full credentials
+        let third: u32 = call_with_auth(&server, &auth, &0_u32, 1, 1, 1)
+            .await
+            .unwrap();
+
+        assert_eq!((first, second, third), (7, 8, 7));
+        assert_eq!(
+            *server.0.lock().unwrap(),
+            ["sys", "short", "rejected", "sys"]
+        );
+    }
+
+    /// A server rejecting every call as too weak, counting the calls.
+    #[derive(Clone)]
+    struct TooWeakServer(Arc<Mutex<u32>>);
+
+    #[async_trait]
+    impl Client for TooWeakServer {
+        async fn call(&self, _body: Request) -> crate::Result<Bytes> {
+            *self.0.lock().unwrap() += 1;
+            let reply = ReplyBody::Denied(RejectedReply::AuthError(AuthError::TooWeak));
+            let msg = RpcMessage::<&[u8], &[u8]>::new(1, MessageType::Reply(reply));
+            Ok(Bytes::from(msg.serialise().unwrap()))
+        }
+    }
+
+    #[tokio::test]
+    async fn no_retry_for_other_auth_errors() {
+        let server = TooWeakServer(Default::default());
+        let auth = Auth::new(Credentials::sys("bench", 1000, 100));
+        auth.verify(&AuthFlavor::AuthShort(&b"1234"[..])).unwrap();
+
+        let ret: crate::Result<u32> = call_with_auth(&server, &auth, &0_u32, 1, 1, 1).await;
+
+        assert!(matches!(ret, Err(Error::RpcAuthError(AuthStat::TooWeak))));
+        assert_eq!(*server.0.lock().unwrap(), 1);
+        assert!(auth.uses_short());
+    }
+
+    #[test]
+    fn unexpected_verifier() {
+        let auth = Auth::default();
+        let ret = auth.verify(&AuthFlavor::AuthShort(&b"1234"[..]));
+        assert!(matches!(
+            ret,
+            Err(Error::RpcAuthError(AuthStat::InvalidResponseVerifier))
+        ));
+    }
+}
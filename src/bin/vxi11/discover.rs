@@ -0,0 +1,23 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use async_vxi11::core::client::{PROG, VERS};
+use async_vxi11::portmapper::{self, Mapping, GETPORT};
+use async_vxi11::rpc;
+
+/// Broadcast a port mapper GETPORT request for the VXI-11 core channel to `addr` and
+/// return all servers replying within `timeout` together with their port.
+pub fn discover(addr: IpAddr, timeout: Duration) -> async_vxi11::Result<Vec<(IpAddr, u16)>> {
+    let addr = SocketAddr::new(addr, portmapper::PORT);
+    let replies = rpc::broadcast_blocking(addr, &GETPORT, &Mapping::tcp(PROG, VERS), timeout)?;
+    let mut ret: Vec<(IpAddr, u16)> = Vec::new();
+    for (from, port) in replies {
+        if port == 0 || port > u16::MAX as u32 {
+            continue;
+        }
+        if !ret.iter().any(|(x, _)| *x == from.ip()) {
+            ret.push((from.ip(), port as u16));
+        }
+    }
+    Ok(ret)
+}
@@ -0,0 +1,179 @@
+//! Command-line tool for ad-hoc interaction with VXI-11 instruments.
+//!
+//! Instruments are given as VISA resource string (e.g. `TCPIP0::10.0.0.5::inst0::INSTR`)
+//! or as host name, in which case `--device` selects the device name.
+
+use std::io::Write;
+use std::net::IpAddr;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use async_vxi11::core::client::DEFAULT_DEVICE;
+use async_vxi11::tokio::TcpClient;
+use async_vxi11::CoreClient;
+use clap::{Parser, Subcommand};
+use tokio::runtime::Runtime;
+
+use crate::resource::Resource;
+
+mod discover;
+mod repl;
+mod resource;
+
+#[derive(Parser)]
+#[command(name = "vxi11", version, about = "Interact with VXI-11 instruments")]
+struct Cli {
+    /// IO timeout in milliseconds
+    #[arg(long, global = true, default_value_t = 1000)]
+    io_timeout: u64,
+
+    /// Lock timeout in milliseconds
+    #[arg(long, global = true, default_value_t = 0)]
+    lock_timeout: u64,
+
+    /// Device name, if the instrument is not given as VISA resource string
+    #[arg(long, global = true)]
+    device: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Write a command and print the response
+    Query { resource: String, command: String },
+    /// Write a command
+    Write { resource: String, command: String },
+    /// Read a response
+    Read {
+        resource: String,
+        /// Write the raw response to stdout
+        #[arg(long)]
+        binary: bool,
+    },
+    /// Read the status byte
+    Stb { resource: String },
+    /// Send a device clear
+    Clear { resource: String },
+    /// Find VXI-11 servers by broadcasting a port mapper request
+    Discover {
+        /// Broadcast address, e.g. 10.0.0.255
+        address: IpAddr,
+        /// Time to wait for replies in milliseconds
+        #[arg(long, default_value_t = 1000)]
+        timeout: u64,
+    },
+    /// Start an interactive session
+    Repl { resource: String },
+}
+
+impl Cli {
+    fn resource(&self, resource: &str) -> Result<Resource, String> {
+        let device = self.device.as_deref().unwrap_or(DEFAULT_DEVICE);
+        Resource::parse(resource, device)
+    }
+
+    async fn connect(&self, resource: &str) -> Result<CoreClient<TcpClient>, String> {
+        let resource = self.resource(resource)?;
+        let addr = resource.resolve()?;
+        let mut client = CoreClient::connect_device(addr, &resource.device)
+            .await
+            .map_err(|err| format!("Cannot connect to {}: {}", resource, err))?;
+        client.options.io_timeout = Duration::from_millis(self.io_timeout);
+        client.options.lock_timeout = Duration::from_millis(self.lock_timeout);
+        Ok(client)
+    }
+}
+
+/// Append a newline to `command` unless it is already terminated.
+pub(crate) fn terminate(command: &str) -> Vec<u8> {
+    let mut ret = command.as_bytes().to_vec();
+    if !command.ends_with('\n') {
+        ret.push(b'\n');
+    }
+    ret
+}
+
+/// Print a response received from the instrument as text.
+pub(crate) fn print_text(data: &[u8]) {
+    let text = String::from_utf8_lossy(data);
+    println!("{}", text.trim_end_matches(&['\r', '\n'][..]));
+}
+
+fn run(cli: &Cli, rt: &Runtime) -> Result<(), String> {
+    match &cli.command {
+        Command::Query { resource, command } => rt.block_on(async {
+            let mut client = cli.connect(resource).await?;
+            client
+                .device_write(terminate(command))
+                .await
+                .map_err(|err| err.to_string())?;
+            let data = client.device_read().await.map_err(|err| err.to_string())?;
+            print_text(&data);
+            Ok(())
+        }),
+        Command::Write { resource, command } => rt.block_on(async {
+            let mut client = cli.connect(resource).await?;
+            client
+                .device_write(terminate(command))
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        }),
+        Command::Read { resource, binary } => rt.block_on(async {
+            let mut client = cli.connect(resource).await?;
+            let data = client.device_read().await.map_err(|err| err.to_string())?;
+            if *binary {
+                let mut stdout = std::io::stdout();
+                stdout
+                    .write_all(&data)
+                    .and_then(|_| stdout.flush())
+                    .map_err(|err| err.to_string())?;
+            } else {
+                print_text(&data);
+            }
+            Ok(())
+        }),
+        Command::Stb { resource } => rt.block_on(async {
+            let mut client = cli.connect(resource).await?;
+            let stb = client
+                .device_readstb()
+                .await
+                .map_err(|err| err.to_string())?;
+            println!("{}", stb);
+            Ok(())
+        }),
+        Command::Clear { resource } => rt.block_on(async {
+            let mut client = cli.connect(resource).await?;
+            client.device_clear().await.map_err(|err| err.to_string())
+        }),
+        Command::Discover { address, timeout } => {
+            let servers = discover::discover(*address, Duration::from_millis(*timeout))
+                .map_err(|err| err.to_string())?;
+            for (addr, port) in servers {
+                println!("{}\t{}", addr, port);
+            }
+            Ok(())
+        }
+        Command::Repl { resource } => {
+            let client = rt.block_on(cli.connect(resource))?;
+            repl::run(rt, client)
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+        .expect("Cannot create runtime");
+    match run(&cli, &rt) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::path::PathBuf;
+
+use async_vxi11::tokio::TcpClient;
+use async_vxi11::CoreClient;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use tokio::runtime::Runtime;
+
+use crate::{print_text, terminate};
+
+const HELP: &str = "\
+Lines ending with '?' are queries, all other lines are written to the instrument.
+  :read   read a response
+  :stb    read the status byte
+  :clear  send a device clear
+  :help   show this help
+  :quit   exit";
+
+fn history_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".vxi11_history"))
+}
+
+/// Run an interactive session with the instrument.
+pub fn run(rt: &Runtime, mut client: CoreClient<TcpClient>) -> Result<(), String> {
+    let mut editor = DefaultEditor::new().map_err(|err| err.to_string())?;
+    let history = history_file();
+    if let Some(history) = &history {
+        let _ = editor.load_history(history);
+    }
+    println!("Connected to {}. Type :help for help.", client.device());
+    loop {
+        let line = match editor.readline("> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.to_string()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line);
+        let ret = match line {
+            ":quit" | ":q" => break,
+            ":help" => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            ":read" => rt.block_on(client.device_read()).map(|x| print_text(&x)),
+            ":stb" => rt
+                .block_on(client.device_readstb())
+                .map(|x| println!("{}", x)),
+            ":clear" => rt.block_on(client.device_clear()),
+            query if query.ends_with('?') => rt.block_on(async {
+                client.device_write(terminate(query)).await?;
+                let data = client.device_read().await?;
+                print_text(&data);
+                Ok(())
+            }),
+            command => rt
+                .block_on(client.device_write(terminate(command)))
+                .map(|_| ()),
+        };
+        if let Err(err) = ret {
+            eprintln!("Error: {}", err);
+        }
+    }
+    if let Some(history) = &history {
+        let _ = editor.save_history(history);
+    }
+    Ok(())
+}
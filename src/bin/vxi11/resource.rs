@@ -0,0 +1,174 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr, ToSocketAddrs};
+use std::str::FromStr;
+
+use async_vxi11::core::client::DEFAULT_DEVICE;
+
+/// An instrument given either as VISA resource string (`TCPIP0::10.0.0.5::inst0::INSTR`)
+/// or as host name or address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Resource {
+    pub host: String,
+    pub device: String,
+}
+
+impl Resource {
+    /// Resolve the host to an IP address.
+    pub fn resolve(&self) -> Result<IpAddr, String> {
+        (self.host.as_str(), 0)
+            .to_socket_addrs()
+            .map_err(|err| format!("Cannot resolve {}: {}", self.host, err))?
+            .next()
+            .map(|x| x.ip())
+            .ok_or_else(|| format!("Cannot resolve {}", self.host))
+    }
+}
+
+impl Resource {
+    /// Parse a resource, using `device` if it is given as plain host name or address.
+    pub fn parse(s: &str, device: &str) -> Result<Self, String> {
+        if let Some(host) = plain_host(s) {
+            return Ok(Resource {
+                host: host.to_string(),
+                device: device.to_string(),
+            });
+        }
+        let (interface, rest) = s
+            .split_once("::")
+            .ok_or_else(|| format!("Invalid resource: {}", s))?;
+        if !interface.to_uppercase().starts_with("TCPIP") {
+            return Err(format!("Not a TCPIP resource: {}", s));
+        }
+        // IPv6 addresses are enclosed in brackets
+        let (host, rest) = match rest.strip_prefix('[') {
+            Some(rest) => {
+                let (host, rest) = rest
+                    .split_once(']')
+                    .ok_or_else(|| format!("Invalid resource: {}", s))?;
+                match rest.strip_prefix("::") {
+                    Some(rest) => (host, rest),
+                    None if rest.is_empty() => (host, rest),
+                    None => return Err(format!("Invalid resource: {}", s)),
+                }
+            }
+            None => rest.split_once("::").unwrap_or((rest, "")),
+        };
+        if host.is_empty() {
+            return Err(format!("Not a TCPIP resource: {}", s));
+        }
+        let parts: Vec<&str> = if rest.is_empty() {
+            Vec::new()
+        } else {
+            rest.split("::").collect()
+        };
+        let mut rest = &parts[..];
+        if let Some((last, init)) = rest.split_last() {
+            match last.to_uppercase().as_str() {
+                "INSTR" => rest = init,
+                "SOCKET" => return Err(format!("SOCKET resources are not supported: {}", s)),
+                _ => {}
+            }
+        }
+        let device = match rest {
+            [] => DEFAULT_DEVICE,
+            [device] => device,
+            _ => return Err(format!("Invalid resource: {}", s)),
+        };
+        Ok(Resource {
+            host: host.to_string(),
+            device: device.to_string(),
+        })
+    }
+}
+
+/// The host if `s` is a plain host name or address rather than a VISA resource string.
+fn plain_host(s: &str) -> Option<&str> {
+    if let Some(host) = s.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+        return Some(host);
+    }
+    if !s.contains("::") || s.parse::<Ipv6Addr>().is_ok() {
+        return Some(s);
+    }
+    None
+}
+
+impl FromStr for Resource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Resource::parse(s, DEFAULT_DEVICE)
+    }
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "TCPIP::[{}]::{}::INSTR", self.host, self.device)
+        } else {
+            write!(f, "TCPIP::{}::{}::INSTR", self.host, self.device)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resource(host: &str, device: &str) -> Resource {
+        Resource {
+            host: host.to_string(),
+            device: device.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("10.0.0.5".parse(), Ok(resource("10.0.0.5", "inst0")));
+        assert_eq!(
+            "TCPIP0::10.0.0.5::INSTR".parse(),
+            Ok(resource("10.0.0.5", "inst0"))
+        );
+        assert_eq!(
+            "TCPIP::gw.lab::gpib0,5::INSTR".parse(),
+            Ok(resource("gw.lab", "gpib0,5"))
+        );
+        assert_eq!(
+            "tcpip1::10.0.0.5::inst1".parse(),
+            Ok(resource("10.0.0.5", "inst1"))
+        );
+        assert!("TCPIP0::10.0.0.5::5025::SOCKET"
+            .parse::<Resource>()
+            .is_err());
+        assert!("GPIB0::5::INSTR".parse::<Resource>().is_err());
+        assert!("TCPIP0::::INSTR".parse::<Resource>().is_err());
+    }
+
+    #[test]
+    fn parse_ipv6() {
+        assert_eq!("::1".parse(), Ok(resource("::1", "inst0")));
+        assert_eq!("fe80::1".parse(), Ok(resource("fe80::1", "inst0")));
+        assert_eq!("[fe80::1]".parse(), Ok(resource("fe80::1", "inst0")));
+        assert_eq!(
+            "TCPIP0::[fe80::1]::gpib0,5::INSTR".parse(),
+            Ok(resource("fe80::1", "gpib0,5"))
+        );
+        assert_eq!("TCPIP0::[::1]".parse(), Ok(resource("::1", "inst0")));
+        assert!("TCPIP0::[::1::INSTR".parse::<Resource>().is_err());
+        assert_eq!(
+            resource("::1", "inst0").to_string(),
+            "TCPIP::[::1]::inst0::INSTR"
+        );
+    }
+
+    #[test]
+    fn device_option() {
+        assert_eq!(
+            Resource::parse("10.0.0.5", "gpib0,5"),
+            Ok(resource("10.0.0.5", "gpib0,5"))
+        );
+        assert_eq!(
+            Resource::parse("TCPIP::gw.lab::gpib0,7::INSTR", "gpib0,5"),
+            Ok(resource("gw.lab", "gpib0,7"))
+        );
+    }
+}
@@ -0,0 +1,320 @@
+//! Blocking client API for use without an async runtime.
+//!
+//! [`TcpClient`] implements the transport over a [`std::net::TcpStream`] and performs all
+//! IO synchronously with socket timeouts. [`CoreClient`] drives the async
+//! [`crate::CoreClient`] on top of it, such that both share the same implementation.
+//! Since the transport never suspends, the futures complete within a single poll.
+//! The async [`Client`] implementation driving them is private to this module, so the
+//! blocking transport cannot end up on an async executor.
+
+use std::io::{Cursor, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::executor::block_on;
+use onc_rpc::{MessageType, RpcMessage};
+
+use crate::core::client::{Connection, DEFAULT_DEVICE, PROG, VERS};
+use crate::portmapper::get_port;
+use crate::record::{fragment, read_record_blocking, RecordLimits};
+use crate::rpc::{message_xid, Client, Request};
+use crate::{Error, ReadOptions, ReadReason, VxiOptions, WriteOptions};
+
+/// Default timeout for connecting and for socket reads and writes.
+pub const DEFAULT_SOCKET_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A blocking RPC connection over TCP. Calls are performed one after another.
+#[derive(Clone)]
+pub struct TcpClient {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    stream: TcpStream,
+    xid: u32,
+    limits: RecordLimits,
+    // set if an IO error occured, as the stream might be in the middle of a record
+    broken: bool,
+}
+
+impl TcpClient {
+    /// Connect to `addr`. `timeout` is used for connecting as well as the read and write
+    /// timeouts of the socket.
+    pub fn connect<T: Into<SocketAddr>>(addr: T, timeout: Option<Duration>) -> crate::Result<Self> {
+        let addr = addr.into();
+        let stream = match timeout {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(addr),
+        }
+        .map_err(Error::Io)?;
+        stream.set_nodelay(true).map_err(Error::Io)?;
+        let ret = Self {
+            inner: Arc::new(Mutex::new(Inner {
+                stream,
+                xid: 0,
+                limits: RecordLimits::default(),
+                broken: false,
+            })),
+        };
+        ret.set_timeout(timeout)?;
+        Ok(ret)
+    }
+
+    /// Connect to the server with the portmapper protocol.
+    pub fn connect_with_mapper_timeout<T: Into<IpAddr>>(
+        addr: T,
+        prog: u32,
+        vers: u32,
+        timeout: Option<Duration>,
+    ) -> crate::Result<Self> {
+        let addr = addr.into();
+        let mapper_client = TcpClient::connect(SocketAddr::new(addr, 111), timeout)?;
+        let port = block_on(get_port(&Transport(mapper_client), prog, vers))?;
+        TcpClient::connect(SocketAddr::new(addr, port), timeout)
+    }
+
+    /// Set the read and write timeouts of the socket.
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> crate::Result<()> {
+        let inner = self.inner.lock().unwrap();
+        inner.stream.set_read_timeout(timeout).map_err(Error::Io)?;
+        inner.stream.set_write_timeout(timeout).map_err(Error::Io)
+    }
+
+    /// Set the size limits of records.
+    pub fn set_record_limits(&self, limits: RecordLimits) {
+        self.inner.lock().unwrap().limits = limits;
+    }
+
+    fn call_blocking(&self, body: Request) -> crate::Result<Bytes> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.broken {
+            return Err(Error::ConnectionClosed);
+        }
+        inner.xid = inner.xid.wrapping_add(1);
+        let xid = inner.xid;
+
+        let msg = RpcMessage::new(xid, MessageType::Call(body));
+        let buf = Vec::with_capacity(msg.serialised_len() as usize);
+        let mut cursor = Cursor::new(buf);
+        msg.serialise_into(&mut cursor).map_err(Error::Io)?;
+
+        let data = fragment(cursor.into_inner(), &inner.limits);
+        let ret = inner.transact(xid, &data);
+        if ret.is_err() {
+            inner.broken = true;
+        }
+        ret
+    }
+}
+
+impl Inner {
+    fn transact(&mut self, xid: u32, data: &[u8]) -> crate::Result<Bytes> {
+        self.stream.write_all(data).map_err(Error::Io)?;
+        let reply = read_record_blocking(&mut self.stream, &self.limits)?;
+        // calls are not pipelined and the connection is dropped after any error, so the
+        // reply must belong to this call
+        match message_xid(&reply) {
+            Some(x) if x == xid => Ok(reply),
+            Some(actual) => Err(Error::UnexpectedXid {
+                expected: xid,
+                actual,
+            }),
+            None => Err(Error::CannotUnpack),
+        }
+    }
+}
+
+/// The [`Client`] driving the async implementation over a [`TcpClient`]. Its calls block
+/// on socket IO despite being async, so it must only be polled with `block_on` by this
+/// module and never be exposed.
+#[derive(Clone)]
+struct Transport(TcpClient);
+
+#[async_trait]
+impl Client for Transport {
+    async fn call(&self, body: Request) -> crate::Result<Bytes> {
+        self.0.call_blocking(body)
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.0.inner.lock().unwrap().stream.peer_addr().ok()
+    }
+}
+
+/// Blocking version of [`crate::CoreClient`].
+pub struct CoreClient {
+    inner: crate::CoreClient<Transport>,
+}
+
+impl CoreClient {
+    pub fn connect<A: Into<IpAddr>>(addr: A) -> crate::Result<Self> {
+        Self::connect_device(addr, DEFAULT_DEVICE)
+    }
+
+    /// Connect to the server and create a link to the given `device`.
+    pub fn connect_device<A: Into<IpAddr>>(addr: A, device: &str) -> crate::Result<Self> {
+        Self::connect_with_timeout(addr, device, Some(DEFAULT_SOCKET_TIMEOUT))
+    }
+
+    /// Connect to the server and create a link to the given `device`, using `timeout`
+    /// as socket timeout.
+    pub fn connect_with_timeout<A: Into<IpAddr>>(
+        addr: A,
+        device: &str,
+        timeout: Option<Duration>,
+    ) -> crate::Result<Self> {
+        let client = TcpClient::connect_with_mapper_timeout(addr, PROG, VERS, timeout)?;
+        Self::from_client(client, device)
+    }
+
+    /// Create a link to `device` over an already connected `client`.
+    pub fn from_client(client: TcpClient, device: &str) -> crate::Result<Self> {
+        let inner = block_on(Connection::from_client(Transport(client)).create_link(device))?;
+        Ok(Self { inner })
+    }
+
+    pub fn options(&self) -> &VxiOptions {
+        &self.inner.options
+    }
+
+    pub fn options_mut(&mut self) -> &mut VxiOptions {
+        &mut self.inner.options
+    }
+
+    /// The name of the device this link was created for.
+    pub fn device(&self) -> &str {
+        self.inner.device()
+    }
+
+    /// The link id assigned by the server.
+    pub fn link_id(&self) -> u32 {
+        self.inner.link_id()
+    }
+
+    pub fn destroy_link(self) -> crate::Result<()> {
+        block_on(self.inner.destroy_link())
+    }
+
+    /// Refer to [`crate::CoreClient::device_write()`].
+    pub fn device_write(&mut self, data: Vec<u8>) -> crate::Result<usize> {
+        block_on(self.inner.device_write(data))
+    }
+
+    /// Refer to [`crate::CoreClient::device_write_with()`].
+    pub fn device_write_with(
+        &mut self,
+        data: Vec<u8>,
+        options: WriteOptions,
+    ) -> crate::Result<usize> {
+        block_on(self.inner.device_write_with(data, options))
+    }
+
+    /// Refer to [`crate::CoreClient::device_read()`].
+    pub fn device_read(&mut self) -> crate::Result<Vec<u8>> {
+        block_on(self.inner.device_read())
+    }
+
+    /// Refer to [`crate::CoreClient::device_read_with()`].
+    pub fn device_read_with(
+        &mut self,
+        options: ReadOptions,
+    ) -> crate::Result<(Vec<u8>, ReadReason)> {
+        block_on(self.inner.device_read_with(options))
+    }
+
+    /// Refer to [`crate::CoreClient::device_read_bytes()`].
+    pub fn device_read_bytes(
+        &mut self,
+        options: ReadOptions,
+    ) -> crate::Result<(Bytes, ReadReason)> {
+        block_on(self.inner.device_read_bytes(options))
+    }
+
+    /// Refer to [`crate::CoreClient::device_readstb()`].
+    pub fn device_readstb(&mut self) -> crate::Result<u8> {
+        block_on(self.inner.device_readstb())
+    }
+
+    /// Refer to [`crate::CoreClient::device_clear()`].
+    pub fn device_clear(&mut self) -> crate::Result<()> {
+        block_on(self.inner.device_clear())
+    }
+
+    /// Refer to [`crate::CoreClient::docmd()`].
+    pub fn docmd(
+        &mut self,
+        cmd: u32,
+        network_order: bool,
+        data_size: u32,
+        data_in: Vec<u8>,
+    ) -> crate::Result<Vec<u8>> {
+        block_on(self.inner.docmd(cmd, network_order, data_size, data_in))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::client::{CALL_CREATE_LINK, CALL_DEVICE_READ, CALL_DEVICE_WRITE};
+    use crate::testing::MockServer;
+
+    fn xdr(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|x| x.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn query() {
+        let addr = MockServer::spawn(|mut conn| {
+            let create_link = conn.recv();
+            assert_eq!(create_link.procedure, CALL_CREATE_LINK);
+            conn.reply(create_link.xid, &xdr(&[0, 1, 1234, 1024]));
+            let write = conn.recv();
+            assert_eq!(write.procedure, CALL_DEVICE_WRITE);
+            conn.reply(write.xid, &xdr(&[0, 6]));
+            let read = conn.recv();
+            assert_eq!(read.procedure, CALL_DEVICE_READ);
+            conn.reply(read.xid, &xdr(&[0, 4, 4, u32::from_be_bytes(*b"ACME")]));
+        });
+        let client = TcpClient::connect(addr, Some(DEFAULT_SOCKET_TIMEOUT)).unwrap();
+        let mut client = CoreClient::from_client(client, DEFAULT_DEVICE).unwrap();
+
+        assert_eq!(client.device_write(b"*IDN?\n".to_vec()).unwrap(), 6);
+        assert_eq!(client.device_read().unwrap(), b"ACME");
+    }
+
+    #[test]
+    fn socket_timeout() {
+        let addr = MockServer::spawn(|mut conn| {
+            conn.recv();
+            std::thread::sleep(Duration::from_millis(500));
+        });
+        let client = TcpClient::connect(addr, Some(Duration::from_millis(50))).unwrap();
+
+        let ret = CoreClient::from_client(client.clone(), DEFAULT_DEVICE);
+
+        assert!(matches!(ret, Err(Error::Io(_))));
+        let ret = CoreClient::from_client(client, DEFAULT_DEVICE);
+        assert!(matches!(ret, Err(Error::ConnectionClosed)));
+    }
+
+    #[test]
+    fn unexpected_xid() {
+        let addr = MockServer::spawn(|mut conn| {
+            let call = conn.recv();
+            conn.reply(call.xid + 1, &xdr(&[0, 1, 1234, 1024]));
+        });
+        let client = TcpClient::connect(addr, Some(DEFAULT_SOCKET_TIMEOUT)).unwrap();
+
+        let ret = CoreClient::from_client(client, DEFAULT_DEVICE);
+        assert!(matches!(
+            ret,
+            Err(Error::UnexpectedXid {
+                expected: 1,
+                actual: 2
+            })
+        ));
+    }
+}
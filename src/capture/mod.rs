@@ -0,0 +1,308 @@
+//! Capturing RPC traffic to pcap files and replaying captures.
+//!
+//! [`CaptureClient`] wraps any [`Client`] and records every call and reply, together with
+//! the time it was sent or received, into a pcap file. The records are framed as
+//! segments of a synthetic TCP connection, such that Wireshark dissects them as ONC-RPC
+//! and VXI-11 (use *Decode As* if the port of the server is not recognized).
+//!
+//! [`ReplayClient`] plays such a capture back as a fake instrument, e.g. for regression
+//! tests:
+//!
+//! ```ignore
+//! let client = ReplayClient::open("idn.pcap")?;
+//! let mut link = CoreClient::from_client(client, "inst0").await?;
+//! link.device_write(b"*IDN?\n".to_vec()).await?;
+//! assert_eq!(link.device_read().await?, b"ACME,1234\n");
+//! ```
+
+mod pcap;
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use byteorder::{BigEndian, ByteOrder};
+use bytes::Bytes;
+use onc_rpc::RpcMessage;
+
+use crate::capture::pcap::{read_segments, PcapWriter, Reassembly};
+use crate::rpc::{message_xid, Client, Request};
+use crate::Error;
+
+/// Address of the client in captures.
+const CLIENT_ADDR: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 1), 50000);
+/// Address of the server in captures if the transport cannot tell the actual one.
+const SERVER_ADDR: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 2), 1024);
+
+/// A transport recording all calls and replies of the wrapped client into a pcap file.
+///
+/// The xid of a call is assigned by the wrapped transport and only known from its reply.
+/// Thus, a call is written together with its reply, with the time it was sent. The
+/// packets of concurrent calls may therefore appear out of timestamp order, and calls
+/// which failed are written with xid 0 and without reply.
+pub struct CaptureClient<C> {
+    inner: C,
+    writer: Arc<Mutex<PcapWriter<Box<dyn Write + Send>>>>,
+}
+
+impl<C: Clone> Clone for CaptureClient<C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            writer: self.writer.clone(),
+        }
+    }
+}
+
+impl<C: Client> CaptureClient<C> {
+    /// Record the traffic of `inner` into `out`.
+    pub fn new<W: Write + Send + 'static>(inner: C, out: W) -> crate::Result<Self> {
+        let server = match inner.peer_addr() {
+            Some(SocketAddr::V4(addr)) => addr,
+            _ => SERVER_ADDR,
+        };
+        let out: Box<dyn Write + Send> = Box::new(out);
+        let writer = PcapWriter::new(out, CLIENT_ADDR, server).map_err(Error::Io)?;
+        Ok(Self {
+            inner,
+            writer: Arc::new(Mutex::new(writer)),
+        })
+    }
+
+    /// Record the traffic of `inner` into the file at `path`.
+    pub fn create<P: AsRef<Path>>(inner: C, path: P) -> crate::Result<Self> {
+        let file = File::create(path).map_err(Error::Io)?;
+        Self::new(inner, BufWriter::new(file))
+    }
+
+    /// The wrapped client.
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+}
+
+#[async_trait]
+impl<C: Client> Client for CaptureClient<C> {
+    async fn call(&self, body: Request) -> crate::Result<Bytes> {
+        // the xid is assigned by the transport, thus it is filled in once the reply arrived
+        let mut request = vec![0_u8; 12];
+        body.serialise_into(&mut request).map_err(Error::Io)?;
+        let len = request.len() as u32 - 4;
+        BigEndian::write_u32(&mut request[0..4], 0x80000000 | len);
+        let sent = SystemTime::now();
+
+        let ret = self.inner.call(body).await;
+        let received = SystemTime::now();
+
+        let mut writer = self.writer.lock().unwrap();
+        if let Ok(reply) = &ret {
+            if let Some(xid) = message_xid(reply) {
+                BigEndian::write_u32(&mut request[4..8], xid);
+            }
+        }
+        writer
+            .write_record(true, sent, &request)
+            .map_err(Error::Io)?;
+        if let Ok(reply) = &ret {
+            writer
+                .write_record(false, received, reply)
+                .map_err(Error::Io)?;
+        }
+        ret
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.inner.peer_addr()
+    }
+}
+
+/// A call of a capture together with the reply of the server.
+struct Exchange {
+    prog: u32,
+    vers: u32,
+    procedure: u32,
+    reply: Bytes,
+}
+
+/// A fake server answering calls with the replies of a capture.
+///
+/// The calls must be performed in the order of the capture and are matched by their
+/// program, version and procedure numbers. The arguments of the calls are not compared.
+#[derive(Clone)]
+pub struct ReplayClient {
+    exchanges: Arc<Mutex<Replay>>,
+}
+
+struct Replay {
+    remaining: VecDeque<Exchange>,
+    index: usize,
+}
+
+impl ReplayClient {
+    /// Load the capture at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> crate::Result<Self> {
+        let file = File::open(path).map_err(Error::Io)?;
+        Self::from_reader(BufReader::new(file))
+    }
+
+    /// Load a capture from `input`. Calls to which no reply was captured are skipped.
+    pub fn from_reader<R: std::io::Read>(input: R) -> crate::Result<Self> {
+        let mut streams: HashMap<(SocketAddrV4, SocketAddrV4), Reassembly> = HashMap::new();
+        let mut calls = Vec::new();
+        let mut replies = HashMap::new();
+        for segment in read_segments(input)? {
+            let stream = streams.entry((segment.src, segment.dst)).or_default();
+            stream.push(segment.seq, segment.payload);
+            while let Some(record) = take_record(&mut stream.data) {
+                let msg = RpcMessage::from_bytes(&record).map_err(Error::Rpc)?;
+                if let Some(call) = msg.call_body() {
+                    let key = (segment.src, msg.xid());
+                    calls.push((
+                        key,
+                        call.program(),
+                        call.program_version(),
+                        call.procedure(),
+                    ));
+                } else {
+                    replies.insert((segment.dst, msg.xid()), Bytes::from(record));
+                }
+            }
+        }
+        let remaining = calls
+            .into_iter()
+            .filter_map(|(key, prog, vers, procedure)| {
+                let reply = replies.remove(&key)?;
+                Some(Exchange {
+                    prog,
+                    vers,
+                    procedure,
+                    reply,
+                })
+            })
+            .collect();
+        Ok(Self {
+            exchanges: Arc::new(Mutex::new(Replay {
+                remaining,
+                index: 0,
+            })),
+        })
+    }
+
+    /// The number of calls which have not been replayed yet.
+    pub fn remaining(&self) -> usize {
+        self.exchanges.lock().unwrap().remaining.len()
+    }
+}
+
+#[async_trait]
+impl Client for ReplayClient {
+    async fn call(&self, body: Request) -> crate::Result<Bytes> {
+        let mut replay = self.exchanges.lock().unwrap();
+        let index = replay.index;
+        let exchange = replay.remaining.front().ok_or(Error::ConnectionClosed)?;
+        if (exchange.prog, exchange.vers, exchange.procedure)
+            != (body.program(), body.program_version(), body.procedure())
+        {
+            return Err(Error::ReplayMismatch(index));
+        }
+        replay.index += 1;
+        // unwrap() is ok because we checked for an exchange before
+        Ok(replay.remaining.pop_front().unwrap().reply)
+    }
+}
+
+/// Remove a complete record from the start of `stream` and return it with a single
+/// record marking header.
+fn take_record(stream: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let mut record = vec![0_u8; 4];
+    let mut pos = 0;
+    loop {
+        let header = BigEndian::read_u32(stream.get(pos..pos + 4)?);
+        let len = (header & 0x7fffffff) as usize;
+        record.extend_from_slice(stream.get(pos + 4..pos + 4 + len)?);
+        pos += 4 + len;
+        if header & 0x80000000 != 0 {
+            break;
+        }
+    }
+    stream.drain(..pos);
+    let len = record.len() as u32 - 4;
+    BigEndian::write_u32(&mut record[0..4], 0x80000000 | len);
+    Some(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::client::DEFAULT_DEVICE;
+    use crate::testing::device::{MockClient, MockDevice};
+    use crate::CoreClient;
+
+    /// A writer into a buffer which can be inspected after the capture was written.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    async fn record_query() -> Vec<u8> {
+        let mut device = MockDevice::new(1024, 1024);
+        device.output = b"ACME\n".to_vec();
+        let buffer = SharedBuffer::default();
+        let client = CaptureClient::new(MockClient::new(device), buffer.clone()).unwrap();
+        let mut link = CoreClient::from_client(client, DEFAULT_DEVICE)
+            .await
+            .unwrap();
+        link.device_write(b"*IDN?\n".to_vec()).await.unwrap();
+        assert_eq!(link.device_read().await.unwrap(), b"ACME\n");
+        let ret = buffer.0.lock().unwrap().clone();
+        ret
+    }
+
+    #[tokio::test]
+    async fn replay_capture() {
+        let capture = record_query().await;
+        let client = ReplayClient::from_reader(&capture[..]).unwrap();
+        assert_eq!(client.remaining(), 3);
+
+        let mut link = CoreClient::from_client(client.clone(), DEFAULT_DEVICE)
+            .await
+            .unwrap();
+        assert_eq!(link.device_write(b"*IDN?\n".to_vec()).await.unwrap(), 6);
+        assert_eq!(link.device_read().await.unwrap(), b"ACME\n");
+        assert_eq!(client.remaining(), 0);
+    }
+
+    #[tokio::test]
+    async fn replay_detects_unexpected_call() {
+        let capture = record_query().await;
+        let client = ReplayClient::from_reader(&capture[..]).unwrap();
+        let mut link = CoreClient::from_client(client, DEFAULT_DEVICE)
+            .await
+            .unwrap();
+
+        let ret = link.device_read().await;
+
+        assert!(matches!(ret, Err(Error::ReplayMismatch(1))));
+    }
+
+    #[test]
+    fn records_are_reassembled() {
+        let mut stream = vec![0, 0, 0, 2, 1, 2, 0x80, 0, 0, 1, 3, 0x80];
+        assert_eq!(take_record(&mut stream).unwrap(), [0x80, 0, 0, 3, 1, 2, 3]);
+        assert_eq!(stream, [0x80]);
+        assert!(take_record(&mut stream).is_none());
+    }
+}
@@ -0,0 +1,328 @@
+//! Minimal support for the classic pcap file format.
+//!
+//! RPC records are written as IPv4/TCP segments between two synthetic endpoints, such
+//! that the capture can be dissected by Wireshark. The reader accepts such files as well
+//! as captures of real traffic on Ethernet links, [`Reassembly`] orders their segments by
+//! sequence number and drops retransmitted data.
+
+use std::io;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use crate::Error;
+
+const MAGIC: u32 = 0xa1b2c3d4;
+const MAGIC_NANOS: u32 = 0xa1b23c4d;
+const SNAPLEN: u32 = 0x40000;
+
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_IPV4: u32 = 228;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const IPPROTO_TCP: u8 = 6;
+const IPV4_HEADER_LEN: usize = 20;
+const TCP_HEADER_LEN: usize = 20;
+const TCP_FLAGS_PSH_ACK: u8 = 0x18;
+
+/// Maximum payload of a synthetic segment, limited by the IPv4 total length field.
+const MAX_SEGMENT: usize = u16::MAX as usize - IPV4_HEADER_LEN - TCP_HEADER_LEN;
+
+/// Writes the records of a single synthetic TCP connection to a pcap file.
+pub(crate) struct PcapWriter<W> {
+    out: W,
+    client: SocketAddrV4,
+    server: SocketAddrV4,
+    client_seq: u32,
+    server_seq: u32,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Write the file header to `out`.
+    pub fn new(mut out: W, client: SocketAddrV4, server: SocketAddrV4) -> io::Result<Self> {
+        let mut header = [0_u8; 24];
+        LittleEndian::write_u32(&mut header[0..4], MAGIC);
+        LittleEndian::write_u16(&mut header[4..6], 2);
+        LittleEndian::write_u16(&mut header[6..8], 4);
+        LittleEndian::write_u32(&mut header[16..20], SNAPLEN);
+        LittleEndian::write_u32(&mut header[20..24], LINKTYPE_RAW);
+        out.write_all(&header)?;
+        out.flush()?;
+        Ok(Self {
+            out,
+            client,
+            server,
+            client_seq: 1,
+            server_seq: 1,
+        })
+    }
+
+    /// Write a record (including its record marking header) sent by the client if
+    /// `from_client` is set, by the server otherwise.
+    pub fn write_record(
+        &mut self,
+        from_client: bool,
+        time: SystemTime,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let time = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        for segment in data.chunks(MAX_SEGMENT) {
+            let packet = if from_client {
+                let packet = tcp_packet(
+                    self.client,
+                    self.server,
+                    self.client_seq,
+                    self.server_seq,
+                    segment,
+                );
+                self.client_seq = self.client_seq.wrapping_add(segment.len() as u32);
+                packet
+            } else {
+                let packet = tcp_packet(
+                    self.server,
+                    self.client,
+                    self.server_seq,
+                    self.client_seq,
+                    segment,
+                );
+                self.server_seq = self.server_seq.wrapping_add(segment.len() as u32);
+                packet
+            };
+            let mut header = [0_u8; 16];
+            LittleEndian::write_u32(&mut header[0..4], time.as_secs() as u32);
+            LittleEndian::write_u32(&mut header[4..8], time.subsec_micros());
+            LittleEndian::write_u32(&mut header[8..12], packet.len() as u32);
+            LittleEndian::write_u32(&mut header[12..16], packet.len() as u32);
+            self.out.write_all(&header)?;
+            self.out.write_all(&packet)?;
+        }
+        self.out.flush()
+    }
+}
+
+fn tcp_packet(src: SocketAddrV4, dst: SocketAddrV4, seq: u32, ack: u32, payload: &[u8]) -> Vec<u8> {
+    let total_len = IPV4_HEADER_LEN + TCP_HEADER_LEN + payload.len();
+    let mut packet = vec![0_u8; IPV4_HEADER_LEN + TCP_HEADER_LEN];
+
+    let ip = &mut packet[..IPV4_HEADER_LEN];
+    ip[0] = 0x45;
+    BigEndian::write_u16(&mut ip[2..4], total_len as u16);
+    // don't fragment
+    ip[6] = 0x40;
+    ip[8] = 64;
+    ip[9] = IPPROTO_TCP;
+    ip[12..16].copy_from_slice(&src.ip().octets());
+    ip[16..20].copy_from_slice(&dst.ip().octets());
+    let sum = checksum(0, ip);
+    BigEndian::write_u16(&mut ip[10..12], sum);
+
+    let tcp = &mut packet[IPV4_HEADER_LEN..];
+    BigEndian::write_u16(&mut tcp[0..2], src.port());
+    BigEndian::write_u16(&mut tcp[2..4], dst.port());
+    BigEndian::write_u32(&mut tcp[4..8], seq);
+    BigEndian::write_u32(&mut tcp[8..12], ack);
+    tcp[12] = ((TCP_HEADER_LEN / 4) as u8) << 4;
+    tcp[13] = TCP_FLAGS_PSH_ACK;
+    BigEndian::write_u16(&mut tcp[14..16], u16::MAX);
+
+    // checksum over the pseudo header, the TCP header and the payload
+    let mut pseudo = [0_u8; 12];
+    pseudo[0..4].copy_from_slice(&src.ip().octets());
+    pseudo[4..8].copy_from_slice(&dst.ip().octets());
+    pseudo[9] = IPPROTO_TCP;
+    BigEndian::write_u16(&mut pseudo[10..12], (TCP_HEADER_LEN + payload.len()) as u16);
+    let sum = ones_complement_sum(0, &pseudo);
+    let sum = ones_complement_sum(sum, tcp);
+    let sum = checksum(sum, payload);
+    BigEndian::write_u16(&mut tcp[16..18], sum);
+
+    packet.extend_from_slice(payload);
+    packet
+}
+
+fn ones_complement_sum(mut sum: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for chunk in &mut chunks {
+        sum += BigEndian::read_u16(chunk) as u32;
+    }
+    if let [last] = chunks.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    sum
+}
+
+fn checksum(sum: u32, data: &[u8]) -> u16 {
+    let mut sum = ones_complement_sum(sum, data);
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// The payload of a TCP segment read from a capture.
+pub(crate) struct Segment {
+    pub src: SocketAddrV4,
+    pub dst: SocketAddrV4,
+    pub seq: u32,
+    pub payload: Vec<u8>,
+}
+
+/// Reassembles the data of one direction of a TCP connection from its segments.
+///
+/// The stream starts at the first segment pushed. Data already received is dropped,
+/// segments arriving ahead of the stream are kept until the gap is filled.
+#[derive(Default)]
+pub(crate) struct Reassembly {
+    /// The sequence number of the next byte of the stream.
+    next: Option<u32>,
+    /// The data in order, not yet consumed.
+    pub data: Vec<u8>,
+    early: Vec<(u32, Vec<u8>)>,
+}
+
+impl Reassembly {
+    pub fn push(&mut self, seq: u32, payload: Vec<u8>) {
+        let mut next = *self.next.get_or_insert(seq);
+        self.early.push((seq, payload));
+        // the wrapping distance is negative if a segment starts before the next byte
+        while let Some(index) = self
+            .early
+            .iter()
+            .position(|(seq, _)| seq.wrapping_sub(next) as i32 <= 0)
+        {
+            let (seq, payload) = self.early.swap_remove(index);
+            if let Some(data) = payload.get(next.wrapping_sub(seq) as usize..) {
+                self.data.extend_from_slice(data);
+                next = next.wrapping_add(data.len() as u32);
+            }
+        }
+        self.next = Some(next);
+    }
+}
+
+/// Read all IPv4/TCP segments with a payload from a pcap file.
+pub(crate) fn read_segments<R: Read>(mut input: R) -> crate::Result<Vec<Segment>> {
+    let mut header = [0_u8; 24];
+    input.read_exact(&mut header).map_err(Error::Io)?;
+    let big_endian = match LittleEndian::read_u32(&header[0..4]) {
+        MAGIC | MAGIC_NANOS => false,
+        x if x.swap_bytes() == MAGIC || x.swap_bytes() == MAGIC_NANOS => true,
+        _ => return Err(Error::InvalidCapture),
+    };
+    let read_u32 = |data: &[u8]| {
+        if big_endian {
+            BigEndian::read_u32(data)
+        } else {
+            LittleEndian::read_u32(data)
+        }
+    };
+    let snaplen = read_u32(&header[16..20]);
+    let linktype = read_u32(&header[20..24]);
+
+    let mut ret = Vec::new();
+    loop {
+        let mut header = [0_u8; 16];
+        match input.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(Error::Io(err)),
+        }
+        // bound the allocation for corrupt length fields
+        let incl_len = read_u32(&header[8..12]);
+        if incl_len > snaplen || incl_len > SNAPLEN {
+            return Err(Error::InvalidCapture);
+        }
+        let mut packet = vec![0_u8; incl_len as usize];
+        input.read_exact(&mut packet).map_err(Error::Io)?;
+        let ip = match linktype {
+            LINKTYPE_RAW | LINKTYPE_IPV4 => &packet[..],
+            LINKTYPE_ETHERNET => {
+                if packet.len() < 14 || BigEndian::read_u16(&packet[12..14]) != ETHERTYPE_IPV4 {
+                    continue;
+                }
+                &packet[14..]
+            }
+            _ => return Err(Error::InvalidCapture),
+        };
+        if let Some(segment) = parse_segment(ip) {
+            ret.push(segment);
+        }
+    }
+    Ok(ret)
+}
+
+fn parse_segment(ip: &[u8]) -> Option<Segment> {
+    if ip.len() < IPV4_HEADER_LEN || ip[0] >> 4 != 4 || ip[9] != IPPROTO_TCP {
+        return None;
+    }
+    let ip_header_len = (ip[0] & 0x0f) as usize * 4;
+    let total_len = (BigEndian::read_u16(&ip[2..4]) as usize).min(ip.len());
+    let tcp = ip.get(ip_header_len..total_len)?;
+    if tcp.len() < TCP_HEADER_LEN {
+        return None;
+    }
+    let tcp_header_len = (tcp[12] >> 4) as usize * 4;
+    let payload = tcp.get(tcp_header_len..)?;
+    if payload.is_empty() {
+        return None;
+    }
+    let addr =
+        |offset: usize| Ipv4Addr::new(ip[offset], ip[offset + 1], ip[offset + 2], ip[offset + 3]);
+    Some(Segment {
+        src: SocketAddrV4::new(addr(12), BigEndian::read_u16(&tcp[0..2])),
+        dst: SocketAddrV4::new(addr(16), BigEndian::read_u16(&tcp[2..4])),
+        seq: BigEndian::read_u32(&tcp[4..8]),
+        payload: payload.to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reassembly() {
+        let mut stream = Reassembly::default();
+        stream.push(100, vec![1, 2]);
+        // out of order
+        stream.push(104, vec![5, 6]);
+        assert_eq!(stream.data, [1, 2]);
+        stream.push(102, vec![3, 4]);
+        assert_eq!(stream.data, [1, 2, 3, 4, 5, 6]);
+        // retransmitted, partially overlapping
+        stream.push(102, vec![3, 4]);
+        stream.push(105, vec![6, 7]);
+        assert_eq!(stream.data, [1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn reassembly_wraps() {
+        let mut stream = Reassembly::default();
+        stream.push(u32::MAX, vec![1, 2]);
+        stream.push(u32::MAX, vec![1]);
+        stream.push(1, vec![3]);
+        assert_eq!(stream.data, [1, 2, 3]);
+    }
+
+    #[test]
+    fn oversized_packet() {
+        let mut capture = Vec::new();
+        PcapWriter::new(
+            &mut capture,
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1),
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 2),
+        )
+        .unwrap();
+        let mut header = [0_u8; 16];
+        LittleEndian::write_u32(&mut header[8..12], u32::MAX);
+        capture.extend_from_slice(&header);
+
+        assert!(matches!(
+            read_segments(&capture[..]),
+            Err(Error::InvalidCapture)
+        ));
+    }
+}
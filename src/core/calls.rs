@@ -1,9 +1,10 @@
-use xdr_rs_serialize::de::XDRIn;
-use xdr_rs_serialize::ser::XDROut;
+use bytes::Bytes;
 
-use crate::rpc::{Deserialize, Serialize};
-use crate::Error;
+use async_vxi11_derive::{XdrDeserialize, XdrSerialize};
 
+use crate::xdr::{Decoder, Deserialize, Encoder, Serialize};
+
+#[derive(XdrSerialize, XdrDeserialize)]
 pub struct CreateLinkRequest {
     pub client_id: u32,
     pub lock: bool,
@@ -11,15 +12,7 @@ pub struct CreateLinkRequest {
     pub device: String,
 }
 
-impl Serialize for CreateLinkRequest {
-    fn serialize(&self, out: &mut Vec<u8>) {
-        self.client_id.write_xdr(out).unwrap();
-        self.lock.write_xdr(out).unwrap();
-        self.lock_timeout_ms.write_xdr(out).unwrap();
-        self.device.write_xdr(out).unwrap();
-    }
-}
-
+#[derive(XdrSerialize, XdrDeserialize)]
 pub struct CreateLinkResponse {
     pub error: u32,
     pub link_id: u32,
@@ -27,23 +20,7 @@ pub struct CreateLinkResponse {
     pub max_recv_size: u32,
 }
 
-impl Deserialize for CreateLinkResponse {
-    fn deserialize_partial(data: &[u8]) -> crate::Result<(Self, usize)> {
-        let (error, _) = u32::read_xdr(&data[0..]).map_err(Error::XdrError)?;
-        let (link_id, _) = u32::read_xdr(&data[4_usize..]).map_err(Error::XdrError)?;
-        let (port, _) = u32::read_xdr(&data[8_usize..]).map_err(Error::XdrError)?;
-        let (max_recv_size, _) = u32::read_xdr(&data[12_usize..]).map_err(Error::XdrError)?;
-
-        let ret = CreateLinkResponse {
-            error,
-            link_id,
-            port,
-            max_recv_size,
-        };
-        Ok((ret, 16_usize))
-    }
-}
-
+#[derive(XdrSerialize, XdrDeserialize)]
 pub struct DeviceWriteRequest {
     pub link_id: u32,
     pub io_timeout: u32,
@@ -52,30 +29,13 @@ pub struct DeviceWriteRequest {
     pub data: Vec<u8>,
 }
 
-impl Serialize for DeviceWriteRequest {
-    fn serialize(&self, out: &mut Vec<u8>) {
-        self.link_id.write_xdr(out).unwrap();
-        self.io_timeout.write_xdr(out).unwrap();
-        self.lock_timeout.write_xdr(out).unwrap();
-        self.flags.write_xdr(out).unwrap();
-        self.data.write_xdr(out).unwrap();
-    }
-}
-
+#[derive(XdrSerialize, XdrDeserialize)]
 pub struct DeviceWriteResponse {
     pub error: u32,
     pub size: u32,
 }
 
-impl Deserialize for DeviceWriteResponse {
-    fn deserialize_partial(data: &[u8]) -> crate::Result<(Self, usize)> {
-        let (error, _) = u32::read_xdr(data).map_err(Error::XdrError)?;
-        let (size, _) = u32::read_xdr(&data[4_usize..]).map_err(Error::XdrError)?;
-        let ret = DeviceWriteResponse { error, size };
-        Ok((ret, 8_usize))
-    }
-}
-
+#[derive(XdrSerialize, XdrDeserialize)]
 pub struct DeviceReadRequest {
     pub link_id: u32,
     pub request_size: u32,
@@ -85,33 +45,102 @@ pub struct DeviceReadRequest {
     pub term_char: u32,
 }
 
-impl Serialize for DeviceReadRequest {
+/// Decoded by hand to return the data as a slice of the received reply.
+pub struct DeviceReadResponse {
+    pub error: u32,
+    pub reason: u32,
+    pub data: Bytes,
+}
+
+impl Serialize for DeviceReadResponse {
     fn serialize(&self, out: &mut Vec<u8>) {
-        self.link_id.write_xdr(out).unwrap();
-        self.request_size.write_xdr(out).unwrap();
-        self.io_timeout.write_xdr(out).unwrap();
-        self.lock_timeout.write_xdr(out).unwrap();
-        self.flags.write_xdr(out).unwrap();
-        self.term_char.write_xdr(out).unwrap();
+        let mut encoder = Encoder::new(out);
+        encoder.u32(self.error);
+        encoder.u32(self.reason);
+        encoder.opaque(&self.data);
     }
 }
 
-pub struct DeviceReadResponse {
+impl Deserialize for DeviceReadResponse {
+    fn decode(decoder: &mut Decoder<'_>) -> crate::Result<Self> {
+        Ok(DeviceReadResponse {
+            error: decoder.u32()?,
+            reason: decoder.u32()?,
+            data: Bytes::copy_from_slice(decoder.opaque()?),
+        })
+    }
+
+    fn deserialize_bytes(data: &Bytes) -> crate::Result<Self> {
+        let mut decoder = Decoder::new(data);
+        Ok(DeviceReadResponse {
+            error: decoder.u32()?,
+            reason: decoder.u32()?,
+            data: data.slice_ref(decoder.opaque()?),
+        })
+    }
+}
+
+#[derive(XdrSerialize, XdrDeserialize)]
+pub struct DeviceDocmdRequest {
+    pub link_id: u32,
+    pub flags: u32,
+    pub io_timeout: u32,
+    pub lock_timeout: u32,
+    pub cmd: i32,
+    pub network_order: bool,
+    pub data_size: i32,
+    pub data_in: Vec<u8>,
+}
+
+#[derive(XdrSerialize, XdrDeserialize)]
+pub struct DeviceDocmdResponse {
     pub error: u32,
-    pub reason: u32,
-    pub data: Vec<u8>,
+    pub data_out: Vec<u8>,
 }
 
-impl Deserialize for DeviceReadResponse {
-    fn deserialize_partial(data: &[u8]) -> crate::Result<(Self, usize)> {
-        let (error, _) = u32::read_xdr(data).map_err(Error::XdrError)?;
-        let (reason, _) = u32::read_xdr(&data[4..]).map_err(Error::XdrError)?;
-        let (data, len) = Vec::<u8>::read_xdr(&data[8..]).map_err(Error::XdrError)?;
-        let ret = DeviceReadResponse {
-            error,
-            reason,
-            data,
-        };
-        Ok((ret, (len + 8) as usize))
+#[derive(XdrSerialize, XdrDeserialize)]
+pub struct DeviceGenericRequest {
+    pub link_id: u32,
+    pub flags: u32,
+    pub lock_timeout: u32,
+    pub io_timeout: u32,
+}
+
+#[derive(XdrSerialize, XdrDeserialize)]
+pub struct DeviceReadStbResponse {
+    pub error: u32,
+    pub stb: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    /// Decoding every truncation of a valid reply fails instead of panicking.
+    fn truncated<T: Deserialize>(reply: &[u8]) {
+        assert!(T::deserialize(reply).is_ok());
+        for len in 0..reply.len() {
+            let ret = T::deserialize(&reply[..len]);
+            assert!(matches!(ret, Err(Error::Xdr(_))), "length {}", len);
+        }
+    }
+
+    #[test]
+    fn truncated_replies() {
+        truncated::<CreateLinkResponse>(&[0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3]);
+        truncated::<DeviceWriteResponse>(&[0, 0, 0, 0, 0, 0, 0, 5]);
+        truncated::<DeviceReadResponse>(&[0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 3, 1, 2, 3, 0]);
+        truncated::<DeviceDocmdResponse>(&[0, 0, 0, 0, 0, 0, 0, 1, 7, 0, 0, 0]);
+        truncated::<DeviceReadStbResponse>(&[0, 0, 0, 0, 0, 0, 0, 0x42]);
+    }
+
+    #[test]
+    fn oversized_opaque_length() {
+        let reply = Bytes::from_static(&[0, 0, 0, 0, 0, 0, 0, 4, 0xff, 0xff, 0xff, 0xff, 1, 2]);
+        let ret = DeviceReadResponse::deserialize_bytes(&reply);
+        assert!(matches!(ret, Err(Error::Xdr(_))));
+        let ret = DeviceDocmdResponse::deserialize(&reply[4..]);
+        assert!(matches!(ret, Err(Error::Xdr(_))));
     }
 }
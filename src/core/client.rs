@@ -1,34 +1,170 @@
 use std::net::IpAddr;
+use std::ops::BitOr;
 use std::time::Duration;
 
+use bytes::{Bytes, BytesMut};
+
+use crate::auth::Credentials;
 use crate::core::calls::{
-    CreateLinkRequest, CreateLinkResponse, DeviceReadRequest, DeviceReadResponse,
+    CreateLinkRequest, CreateLinkResponse, DeviceDocmdRequest, DeviceDocmdResponse,
+    DeviceGenericRequest, DeviceReadRequest, DeviceReadResponse, DeviceReadStbResponse,
     DeviceWriteRequest, DeviceWriteResponse,
 };
-use crate::rpc::Client;
-use crate::{rpc, Error};
+use crate::rpc::{Client, Connect, Procedure, RpcClient};
+use crate::trace::{self, link_span};
+use crate::{stats, Error};
+
+/// RPC program number of the VXI-11 core channel.
+pub const PROG: u32 = 0x0607af;
+/// RPC program version of the VXI-11 core channel.
+pub const VERS: u32 = 1;
 
-const PROG: u32 = 0x0607af;
-const VERS: u32 = 1;
+pub(crate) const CALL_CREATE_LINK: u32 = 10;
+pub(crate) const CALL_DESTROY_LINK: u32 = 23;
+pub(crate) const CALL_DEVICE_WRITE: u32 = 11;
+pub(crate) const CALL_DEVICE_READ: u32 = 12;
+pub(crate) const CALL_DEVICE_READSTB: u32 = 13;
+pub(crate) const CALL_DEVICE_CLEAR: u32 = 15;
+pub(crate) const CALL_DEVICE_DOCMD: u32 = 22;
 
-const CALL_CREATE_LINK: u32 = 10;
-const CALL_DESTROY_LINK: u32 = 23;
-const CALL_DEVICE_WRITE: u32 = 11;
-const CALL_DEVICE_READ: u32 = 12;
+const CREATE_LINK: Procedure<CreateLinkRequest, CreateLinkResponse> =
+    Procedure::new(PROG, VERS, CALL_CREATE_LINK);
+const DESTROY_LINK: Procedure<u32, u32> = Procedure::new(PROG, VERS, CALL_DESTROY_LINK);
+const DEVICE_WRITE: Procedure<DeviceWriteRequest, DeviceWriteResponse> =
+    Procedure::new(PROG, VERS, CALL_DEVICE_WRITE);
+const DEVICE_READ: Procedure<DeviceReadRequest, DeviceReadResponse> =
+    Procedure::new(PROG, VERS, CALL_DEVICE_READ);
+const DEVICE_READSTB: Procedure<DeviceGenericRequest, DeviceReadStbResponse> =
+    Procedure::new(PROG, VERS, CALL_DEVICE_READSTB);
+const DEVICE_CLEAR: Procedure<DeviceGenericRequest, u32> =
+    Procedure::new(PROG, VERS, CALL_DEVICE_CLEAR);
+const DEVICE_DOCMD: Procedure<DeviceDocmdRequest, DeviceDocmdResponse> =
+    Procedure::new(PROG, VERS, CALL_DEVICE_DOCMD);
 
 const IO_TIMEOUT_MS: u64 = 1000;
 
-const OP_FLAG_END: u32 = 8;
-const OP_FLAG_TERMCHAR_SET: u32 = 128;
+/// Device name of the default instrument of a VXI-11 server.
+pub const DEFAULT_DEVICE: &str = "inst0";
 
-const RX_CHR: u32 = 2;
-const RX_END: u32 = 4;
+pub(crate) const OP_FLAG_WAIT_LOCK: u32 = 1;
+pub(crate) const OP_FLAG_END: u32 = 8;
+pub(crate) const OP_FLAG_TERMCHAR_SET: u32 = 128;
 
-#[derive(Clone)]
+/// Reason bits reported by the device why a `device_read` call returned.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReadReason(u32);
+
+impl ReadReason {
+    /// The requested number of bytes was transferred.
+    pub const REQCNT: ReadReason = ReadReason(1);
+    /// The termination character was received.
+    pub const CHR: ReadReason = ReadReason(2);
+    /// The END indicator was received.
+    pub const END: ReadReason = ReadReason(4);
+
+    /// Reason flags from the raw `reason` field of a `device_read` response.
+    pub fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    /// The raw value of the `reason` field.
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns true if all bits in `other` are set.
+    pub fn contains(self, other: ReadReason) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns true if any of the bits in `other` are set.
+    pub fn intersects(self, other: ReadReason) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl BitOr for ReadReason {
+    type Output = ReadReason;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        ReadReason(self.0 | rhs.0)
+    }
+}
+
+/// Options for a single [`CoreClient::device_write_with()`] call.
+#[derive(Clone, Debug)]
+pub struct WriteOptions {
+    /// Set the END indicator with the last byte written. Disable this to send a
+    /// message split across multiple writes.
+    pub end: bool,
+    /// Block until the lock on the device becomes available instead of failing
+    /// if another link holds it.
+    pub wait_lock: bool,
+    /// IO timeout for this write. Overrides [`VxiOptions`] if set.
+    pub io_timeout: Option<Duration>,
+    /// Lock timeout for this write. Overrides [`VxiOptions`] if set.
+    pub lock_timeout: Option<Duration>,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            end: true,
+            wait_lock: false,
+            io_timeout: None,
+            lock_timeout: None,
+        }
+    }
+}
+
+/// Options for a single [`CoreClient::device_read_with()`] call.
+#[derive(Clone, Debug)]
+pub struct ReadOptions {
+    /// Read exactly this number of bytes, unless the device terminates the message earlier.
+    /// If `None`, the read continues until END or the termination character is received.
+    /// `Some(0)` is treated as `Some(1)`, a zero-size read is never sent.
+    pub request_size: Option<usize>,
+    /// Termination character for this read. Overrides [`VxiOptions`] if set.
+    pub termchr: Option<u8>,
+    /// If false, return after the first chunk received from the device instead of
+    /// waiting for END or the termination character. The read also ends early if the
+    /// device returns an empty chunk without any reason bit.
+    pub wait_for_end: bool,
+    /// IO timeout for this read. Overrides [`VxiOptions`] if set.
+    pub io_timeout: Option<Duration>,
+    /// Lock timeout for this read. Overrides [`VxiOptions`] if set.
+    pub lock_timeout: Option<Duration>,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            request_size: None,
+            termchr: None,
+            wait_for_end: true,
+            io_timeout: None,
+            lock_timeout: None,
+        }
+    }
+}
+
+/// Options for [`Connection::create_link_with()`].
+#[derive(Clone, Debug, Default)]
+pub struct LinkOptions {
+    /// Acquire the lock on the device when creating the link.
+    pub lock: bool,
+    /// Time to wait for the lock if another link holds it.
+    pub lock_timeout: Duration,
+}
+
+#[derive(Clone, Debug)]
 pub struct VxiOptions {
-    termchr: Option<u8>,
-    lock_timeout: Duration,
-    io_timeout: Duration,
+    /// Termination character for reads
+    pub termchr: Option<u8>,
+    /// Time to wait for a lock held by another link
+    pub lock_timeout: Duration,
+    /// Time the device waits for an operation to complete
+    pub io_timeout: Duration,
 }
 
 impl Default for VxiOptions {
@@ -41,43 +177,150 @@ impl Default for VxiOptions {
     }
 }
 
-pub struct CoreClient<T: Client> {
-    client: T,
-    abort_port: u16,
-    pub options: VxiOptions,
-    max_recv_size: u32,
-    link_id: u32,
+/// A connection to the core channel of a VXI-11 server.
+///
+/// The port mapper lookup is performed once when connecting. Any number of links
+/// (to different devices or to the same device) can then be created over the
+/// connection with [`Connection::create_link()`]. Cloning a `Connection` is cheap and
+/// shares the underlying transport. Calls on different links may run concurrently.
+#[derive(Clone)]
+pub struct Connection<T: Client> {
+    rpc: RpcClient<T>,
     client_id: u32,
 }
 
-impl<T: Client> CoreClient<T> {
+impl<T: Connect> Connection<T> {
     pub async fn connect<A: Into<IpAddr> + Send>(addr: A) -> crate::Result<Self> {
         let client = T::connect_with_mapper(addr, PROG, VERS).await?;
+        stats::connected(&client);
+        Ok(Self::from_client(client))
+    }
+}
 
+impl<T: Client> Connection<T> {
+    /// Use an already connected `client` as core channel.
+    pub fn from_client(client: T) -> Self {
         let rnd1 = rand::random::<u16>() as u32;
         let rnd2 = rand::random::<u16>() as u32;
+        Self {
+            rpc: RpcClient::new(client),
+            client_id: rnd1 + rnd2 + 1,
+        }
+    }
+
+    /// Authenticate the calls of this connection with `credentials`. Links created
+    /// afterwards use the new credentials.
+    pub fn with_credentials(mut self, credentials: Credentials) -> Self {
+        self.rpc = self.rpc.with_credentials(credentials);
+        self
+    }
+
+    /// The transport of this connection.
+    pub fn client(&self) -> &T {
+        self.rpc.client()
+    }
+
+    /// The RPC client of this connection, e.g. to call procedures of other programs
+    /// served over the same transport.
+    pub fn rpc(&self) -> &RpcClient<T> {
+        &self.rpc
+    }
 
-        let mut ret = Self {
-            client,
+    /// Create a new link to `device`, e.g. `inst0` or `gpib0,5`.
+    pub async fn create_link(&self, device: &str) -> crate::Result<Link<T>> {
+        self.create_link_with(device, Default::default()).await
+    }
+
+    /// Create a new link to `device` with `options`, e.g. to lock the device while
+    /// creating the link.
+    pub async fn create_link_with(
+        &self,
+        device: &str,
+        options: LinkOptions,
+    ) -> crate::Result<Link<T>> {
+        let mut ret = CoreClient {
+            conn: self.clone(),
             abort_port: 0,
             options: Default::default(),
             max_recv_size: 0,
-            client_id: rnd1 + rnd2 + 1,
             link_id: 0,
+            device: device.to_string(),
         };
-        ret.create_link(false, Default::default()).await?;
+        ret.create_link(options.lock, options.lock_timeout).await?;
         Ok(ret)
     }
+}
+
+/// A link to a device, created with [`Connection::create_link()`]. Several links may
+/// share the same connection.
+///
+/// `Link` is the name to use in new code. It is the same type as [`CoreClient`], which
+/// remains available together with its `connect` shortcuts opening a connection per link.
+pub type Link<T> = CoreClient<T>;
+
+pub struct CoreClient<T: Client> {
+    conn: Connection<T>,
+    abort_port: u16,
+    pub options: VxiOptions,
+    max_recv_size: u32,
+    link_id: u32,
+    device: String,
+}
+
+impl<T: Connect> CoreClient<T> {
+    pub async fn connect<A: Into<IpAddr> + Send>(addr: A) -> crate::Result<Self> {
+        Self::connect_device(addr, DEFAULT_DEVICE).await
+    }
+
+    /// Connect to the server and create a link to the given `device`, e.g. `inst1`
+    /// or `gpib0,5` for a GPIB instrument behind a gateway.
+    pub async fn connect_device<A: Into<IpAddr> + Send>(
+        addr: A,
+        device: &str,
+    ) -> crate::Result<Self> {
+        Connection::connect(addr).await?.create_link(device).await
+    }
+}
+
+impl<T: Client> CoreClient<T> {
+    /// Create a link to `device` over an already connected `client`.
+    pub async fn from_client(client: T, device: &str) -> crate::Result<Self> {
+        Connection::from_client(client).create_link(device).await
+    }
+
+    /// The connection this link was created on.
+    pub fn connection(&self) -> &Connection<T> {
+        &self.conn
+    }
+
+    /// The name of the device this link was created for.
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    /// The link id assigned by the server.
+    pub fn link_id(&self) -> u32 {
+        self.link_id
+    }
 
     async fn create_link(&mut self, lock: bool, lock_timeout: Duration) -> crate::Result<()> {
+        let span = link_span!("create_link", self);
+        let op = stats::Operation::start("create_link", self);
+        let ret = trace::instrument(&span, self.create_link_inner(lock, lock_timeout)).await;
+        op.finish(&ret);
+        ret?;
+        span.record("link_id", self.link_id);
+        Ok(())
+    }
+
+    async fn create_link_inner(&mut self, lock: bool, lock_timeout: Duration) -> crate::Result<()> {
         let req = CreateLinkRequest {
-            client_id: self.client_id,
+            client_id: self.conn.client_id,
             lock,
             lock_timeout_ms: lock_timeout.as_millis() as u32,
-            device: "inst0".to_string(),
+            device: self.device.clone(),
         };
-        let resp: CreateLinkResponse =
-            rpc::call(&mut self.client, &req, PROG, VERS, CALL_CREATE_LINK).await?;
+        let resp = self.conn.rpc.call(&CREATE_LINK, &req).await?;
         self.link_id = resp.link_id;
         self.max_recv_size = resp.max_recv_size.min(1024 * 1024);
         if resp.port < 65535 {
@@ -92,77 +335,493 @@ impl<T: Client> CoreClient<T> {
         }
     }
 
-    pub async fn destroy_link(mut self) -> crate::Result<()> {
-        let err: u32 = rpc::call(
-            &mut self.client,
-            &self.link_id,
-            PROG,
-            VERS,
-            CALL_DESTROY_LINK,
-        )
-        .await?;
-        if err != 0 {
-            Err(Error::VxiRemoteError(err))
-        } else {
-            Ok(())
-        }
+    pub async fn destroy_link(self) -> crate::Result<()> {
+        let span = link_span!("destroy_link", self);
+        let op = stats::Operation::start("destroy_link", &self);
+        let ret = trace::instrument(&span, async move {
+            let err = self.conn.rpc.call(&DESTROY_LINK, &self.link_id).await?;
+            if err != 0 {
+                Err(Error::VxiRemoteError(err))
+            } else {
+                Ok(())
+            }
+        })
+        .await;
+        op.finish(&ret);
+        ret
     }
 
-    pub async fn device_write(&mut self, data: Vec<u8>) -> crate::Result<()> {
-        let mut data = data;
-        let mut flags = 0_u32;
-        while !data.is_empty() {
+    /// Write `data` to the device and return the number of bytes written.
+    ///
+    /// The data is split into chunks of at most `max_recv_size` bytes. If the device
+    /// accepts only part of a chunk (as reported in the response), the remaining bytes
+    /// are sent again with the next call.
+    pub async fn device_write(&mut self, data: Vec<u8>) -> crate::Result<usize> {
+        self.device_write_with(data, Default::default()).await
+    }
+
+    /// Write `data` to the device with per-call `options` and return the number of bytes
+    /// written. Refer to [`CoreClient::device_write()`].
+    pub async fn device_write_with(
+        &mut self,
+        data: Vec<u8>,
+        options: WriteOptions,
+    ) -> crate::Result<usize> {
+        let span = link_span!("device_write", self);
+        let op = stats::Operation::start("device_write", self);
+        let ret = trace::instrument(&span, self.write_chunks(data, options)).await;
+        op.finish(&ret);
+        let written = ret?;
+        span.record("bytes", written);
+        stats::bytes_written(self, written);
+        Ok(written)
+    }
+
+    async fn write_chunks(&mut self, data: Vec<u8>, options: WriteOptions) -> crate::Result<usize> {
+        let io_timeout = options.io_timeout.unwrap_or(self.options.io_timeout);
+        let lock_timeout = options.lock_timeout.unwrap_or(self.options.lock_timeout);
+        let mut written = 0;
+        while written < data.len() {
             // slice data up in multiple chunks
-            let max_idx = data.len().min(self.max_recv_size as usize);
-            let send: Vec<u8> = data.drain(0..max_idx).collect();
-            if data.is_empty() {
+            let end = data.len().min(written + self.max_recv_size as usize);
+            let mut flags = 0_u32;
+            if options.wait_lock {
+                flags |= OP_FLAG_WAIT_LOCK;
+            }
+            if options.end && end == data.len() {
                 flags |= OP_FLAG_END;
             }
             let request = DeviceWriteRequest {
                 link_id: self.link_id,
-                io_timeout: self.options.io_timeout.as_millis() as u32,
-                lock_timeout: self.options.lock_timeout.as_millis() as u32,
+                io_timeout: io_timeout.as_millis() as u32,
+                lock_timeout: lock_timeout.as_millis() as u32,
                 flags,
-                data: send,
+                data: data[written..end].to_vec(),
             };
-            let resp: DeviceWriteResponse =
-                rpc::call(&mut self.client, &request, PROG, VERS, CALL_DEVICE_WRITE).await?;
+            let resp = self.conn.rpc.call(&DEVICE_WRITE, &request).await?;
             if resp.error != 0 {
                 return Err(Error::VxiRemoteError(resp.error));
             }
+            if resp.size == 0 {
+                return Err(Error::IncompleteWrite(written));
+            }
+            written += (resp.size as usize).min(end - written);
         }
-        Ok(())
+        Ok(written)
     }
 
+    /// Read a message from the device until END or the termination character.
+    ///
+    /// The data is copied into a new `Vec`. Use [`CoreClient::device_read_bytes()`] to
+    /// avoid the copy for large transfers.
     pub async fn device_read(&mut self) -> crate::Result<Vec<u8>> {
-        let request_size = self.max_recv_size;
+        let (data, _) = self.device_read_with(Default::default()).await?;
+        Ok(data)
+    }
+
+    /// Read from the device with per-call `options`. Returns the data together with the
+    /// reason bits of the last chunk received. Like [`CoreClient::device_read()`], the data
+    /// is copied into a new `Vec`.
+    pub async fn device_read_with(
+        &mut self,
+        options: ReadOptions,
+    ) -> crate::Result<(Vec<u8>, ReadReason)> {
+        let (data, reason) = self.device_read_bytes(options).await?;
+        Ok((data.to_vec(), reason))
+    }
+
+    /// Like [`CoreClient::device_read_with()`], but returns the data without copying it
+    /// if it was received in a single chunk.
+    pub async fn device_read_bytes(
+        &mut self,
+        options: ReadOptions,
+    ) -> crate::Result<(Bytes, ReadReason)> {
+        let span = link_span!("device_read", self);
+        let op = stats::Operation::start("device_read", self);
+        let ret = trace::instrument(&span, self.read_chunks(options)).await;
+        op.finish(&ret);
+        let (data, reason) = ret?;
+        span.record("bytes", data.len());
+        span.record("reason", reason.bits());
+        stats::bytes_read(self, data.len());
+        Ok((data, reason))
+    }
+
+    async fn read_chunks(&mut self, options: ReadOptions) -> crate::Result<(Bytes, ReadReason)> {
         let mut flags = 0_u32;
         let mut term_char = 0_u32;
-        if let Some(term) = self.options.termchr {
+        if let Some(term) = options.termchr.or(self.options.termchr) {
             term_char = term as u32;
             flags |= OP_FLAG_TERMCHAR_SET;
         }
+        let wanted = options.request_size.map(|size| size.max(1));
+        let io_timeout = options.io_timeout.unwrap_or(self.options.io_timeout);
+        let lock_timeout = options.lock_timeout.unwrap_or(self.options.lock_timeout);
 
-        let mut ret = Vec::new();
+        let mut chunks = Vec::new();
+        let mut len = 0;
         loop {
+            let request_size = match wanted {
+                Some(size) => (size - len).min(self.max_recv_size as usize) as u32,
+                None => self.max_recv_size,
+            };
             let request = DeviceReadRequest {
                 link_id: self.link_id,
                 request_size,
-                io_timeout: self.options.io_timeout.as_millis() as u32,
-                lock_timeout: self.options.lock_timeout.as_millis() as u32,
+                io_timeout: io_timeout.as_millis() as u32,
+                lock_timeout: lock_timeout.as_millis() as u32,
                 flags,
                 term_char,
             };
-            let resp: DeviceReadResponse =
-                rpc::call(&mut self.client, &request, PROG, VERS, CALL_DEVICE_READ).await?;
+            let resp = self.conn.rpc.call(&DEVICE_READ, &request).await?;
             if resp.error != 0 {
                 return Err(Error::VxiRemoteError(resp.error));
             }
-            ret.extend(resp.data);
-            if resp.reason & (RX_END | RX_CHR) != 0 {
-                break;
+            let reason = ReadReason::from_bits(resp.reason);
+            // a device sending nothing without a reason would keep the loop going forever
+            let stalled = resp.data.is_empty() && reason == ReadReason::default();
+            len += resp.data.len();
+            chunks.push(resp.data);
+            let done = match wanted {
+                Some(size) => len >= size,
+                None => false,
+            };
+            if done
+                || stalled
+                || !options.wait_for_end
+                || reason.intersects(ReadReason::END | ReadReason::CHR)
+            {
+                return Ok((concat(chunks, len), reason));
             }
         }
-        Ok(ret)
+    }
+
+    /// Read the status byte of the device.
+    pub async fn device_readstb(&mut self) -> crate::Result<u8> {
+        let span = link_span!("device_readstb", self);
+        let op = stats::Operation::start("device_readstb", self);
+        let ret = trace::instrument(&span, async {
+            let request = self.generic_request();
+            let resp = self.conn.rpc.call(&DEVICE_READSTB, &request).await?;
+            if resp.error != 0 {
+                return Err(Error::VxiRemoteError(resp.error));
+            }
+            Ok(resp.stb as u8)
+        })
+        .await;
+        op.finish(&ret);
+        ret
+    }
+
+    /// Send a device clear to the device.
+    pub async fn device_clear(&mut self) -> crate::Result<()> {
+        let span = link_span!("device_clear", self);
+        let op = stats::Operation::start("device_clear", self);
+        let ret = trace::instrument(&span, async {
+            let request = self.generic_request();
+            let err = self.conn.rpc.call(&DEVICE_CLEAR, &request).await?;
+            if err != 0 {
+                Err(Error::VxiRemoteError(err))
+            } else {
+                Ok(())
+            }
+        })
+        .await;
+        op.finish(&ret);
+        ret
+    }
+
+    fn generic_request(&self) -> DeviceGenericRequest {
+        DeviceGenericRequest {
+            link_id: self.link_id,
+            flags: 0,
+            lock_timeout: self.options.lock_timeout.as_millis() as u32,
+            io_timeout: self.options.io_timeout.as_millis() as u32,
+        }
+    }
+
+    /// Execute a device specific command (`device_docmd`), e.g. a GPIB bus command on
+    /// a VXI-11 gateway. `data_size` is the size of an individual data element in
+    /// `data_in` and `network_order` specifies whether `data_in` is in network byte order.
+    ///
+    /// Returns the data the device sent back. Refer to [`crate::core::gpib`] for helpers
+    /// implementing the standard GPIB commands.
+    pub async fn docmd(
+        &mut self,
+        cmd: u32,
+        network_order: bool,
+        data_size: u32,
+        data_in: Vec<u8>,
+    ) -> crate::Result<Vec<u8>> {
+        let span = link_span!("docmd", self);
+        let op = stats::Operation::start("docmd", self);
+        let ret = trace::instrument(&span, async {
+            let request = DeviceDocmdRequest {
+                link_id: self.link_id,
+                flags: 0,
+                io_timeout: self.options.io_timeout.as_millis() as u32,
+                lock_timeout: self.options.lock_timeout.as_millis() as u32,
+                cmd: cmd as i32,
+                network_order,
+                data_size: data_size as i32,
+                data_in,
+            };
+            let resp = self.conn.rpc.call(&DEVICE_DOCMD, &request).await?;
+            if resp.error != 0 {
+                return Err(Error::VxiRemoteError(resp.error));
+            }
+            Ok(resp.data_out)
+        })
+        .await;
+        op.finish(&ret);
+        let data_out = ret?;
+        span.record("bytes", data_out.len());
+        Ok(data_out)
+    }
+}
+
+/// Join the `chunks` with a total length of `len`, avoiding a copy for a single chunk.
+fn concat(mut chunks: Vec<Bytes>, len: usize) -> Bytes {
+    if chunks.len() == 1 {
+        return chunks.pop().unwrap();
+    }
+    let mut ret = BytesMut::with_capacity(len);
+    for chunk in chunks {
+        ret.extend_from_slice(&chunk);
+    }
+    ret.freeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::device::{MockClient, MockDevice};
+
+    #[tokio::test]
+    async fn device_write_resends_unaccepted_tail() {
+        let device = MockDevice::new(3, 8);
+        let mut client = CoreClient::from_client(MockClient::new(device), DEFAULT_DEVICE)
+            .await
+            .unwrap();
+        let data: Vec<u8> = (0..20).collect();
+
+        let written = client.device_write(data.clone()).await.unwrap();
+
+        assert_eq!(written, data.len());
+        assert_eq!(client.conn.client().device().received, data);
+        // only the chunks containing the last byte carry the END flag
+        let end = OP_FLAG_END;
+        assert_eq!(
+            client.conn.client().device().flags,
+            [0, 0, 0, 0, end, end, end]
+        );
+    }
+
+    #[tokio::test]
+    async fn device_write_fails_if_device_stalls() {
+        let device = MockDevice::new(0, 8);
+        let mut client = CoreClient::from_client(MockClient::new(device), DEFAULT_DEVICE)
+            .await
+            .unwrap();
+
+        let ret = client.device_write(vec![1, 2, 3]).await;
+
+        assert!(matches!(ret, Err(Error::IncompleteWrite(0))));
+    }
+
+    #[tokio::test]
+    async fn device_read_with_request_size() {
+        let mut device = MockDevice::new(0, 4);
+        device.output = (0..20).collect();
+        let mut client = CoreClient::from_client(MockClient::new(device), DEFAULT_DEVICE)
+            .await
+            .unwrap();
+
+        let options = ReadOptions {
+            request_size: Some(10),
+            ..Default::default()
+        };
+        let (data, reason) = client.device_read_with(options).await.unwrap();
+
+        assert_eq!(data, (0..10).collect::<Vec<u8>>());
+        assert_eq!(reason, ReadReason::REQCNT);
+        assert_eq!(client.conn.client().device().read_sizes, [4, 4, 2]);
+
+        let data = client.device_read().await.unwrap();
+        assert_eq!(data, (10..20).collect::<Vec<u8>>());
+    }
+
+    #[tokio::test]
+    async fn device_read_with_zero_request_size() {
+        let mut device = MockDevice::new(0, 4);
+        device.output = (0..20).collect();
+        let mut client = CoreClient::from_client(MockClient::new(device), DEFAULT_DEVICE)
+            .await
+            .unwrap();
+
+        let options = ReadOptions {
+            request_size: Some(0),
+            ..Default::default()
+        };
+        let (data, _) = client.device_read_with(options).await.unwrap();
+
+        assert_eq!(data, [0]);
+        assert_eq!(client.conn.client().device().read_sizes, [1]);
+    }
+
+    #[tokio::test]
+    async fn device_read_stops_on_stalled_device() {
+        let mut device = MockDevice::new(0, 4);
+        device.stalled = true;
+        let mut client = CoreClient::from_client(MockClient::new(device), DEFAULT_DEVICE)
+            .await
+            .unwrap();
+
+        let (data, reason) = client.device_read_with(Default::default()).await.unwrap();
+        assert!(data.is_empty());
+        assert_eq!(reason, ReadReason::default());
+
+        let options = ReadOptions {
+            request_size: Some(10),
+            ..Default::default()
+        };
+        let (data, _) = client.device_read_with(options).await.unwrap();
+        assert!(data.is_empty());
+        assert_eq!(client.conn.client().device().read_sizes, [4, 4]);
+    }
+
+    #[tokio::test]
+    async fn device_read_with_timeouts() {
+        let mut device = MockDevice::new(0, 4);
+        device.output = (0..8).collect();
+        let mut client = CoreClient::from_client(MockClient::new(device), DEFAULT_DEVICE)
+            .await
+            .unwrap();
+        client.options.lock_timeout = Duration::from_millis(20);
+
+        let options = ReadOptions {
+            io_timeout: Some(Duration::from_millis(5000)),
+            ..Default::default()
+        };
+        client.device_read_with(options).await.unwrap();
+
+        assert_eq!(
+            client.conn.client().device().read_timeouts,
+            [(5000, 20), (5000, 20)]
+        );
+    }
+
+    #[tokio::test]
+    async fn device_read_with_first_chunk_only() {
+        let mut device = MockDevice::new(0, 4);
+        device.output = (0..20).collect();
+        let mut client = CoreClient::from_client(MockClient::new(device), DEFAULT_DEVICE)
+            .await
+            .unwrap();
+
+        let options = ReadOptions {
+            wait_for_end: false,
+            ..Default::default()
+        };
+        let (data, reason) = client.device_read_with(options).await.unwrap();
+
+        assert_eq!(data, [0, 1, 2, 3]);
+        assert!(!reason.contains(ReadReason::END));
+    }
+
+    #[tokio::test]
+    async fn device_read_bytes_joins_chunks() {
+        let mut device = MockDevice::new(0, 8);
+        device.output = (0..20).collect();
+        let mut client = CoreClient::from_client(MockClient::new(device), DEFAULT_DEVICE)
+            .await
+            .unwrap();
+
+        let (data, reason) = client.device_read_bytes(Default::default()).await.unwrap();
+
+        assert_eq!(data, (0..20).collect::<Vec<u8>>());
+        assert!(reason.contains(ReadReason::END));
+        assert_eq!(client.conn.client().device().read_sizes, [8, 8, 8]);
+    }
+
+    #[tokio::test]
+    async fn device_write_with_options() {
+        let device = MockDevice::new(8, 8);
+        let mut client = CoreClient::from_client(MockClient::new(device), DEFAULT_DEVICE)
+            .await
+            .unwrap();
+
+        let options = WriteOptions {
+            end: false,
+            wait_lock: true,
+            ..Default::default()
+        };
+        client
+            .device_write_with(b"VOLT".to_vec(), options)
+            .await
+            .unwrap();
+        client.device_write(b" 1.0".to_vec()).await.unwrap();
+
+        assert_eq!(client.conn.client().device().received, b"VOLT 1.0");
+        assert_eq!(
+            client.conn.client().device().flags,
+            [OP_FLAG_WAIT_LOCK, OP_FLAG_END]
+        );
+    }
+
+    #[tokio::test]
+    async fn docmd() {
+        let device = MockDevice::new(8, 8);
+        let mut client = CoreClient::from_client(MockClient::new(device), DEFAULT_DEVICE)
+            .await
+            .unwrap();
+
+        let data_out = client.docmd(0x020001, true, 2, vec![0, 7]).await.unwrap();
+
+        assert_eq!(data_out, [0, 2, 0, 1, 7, 0]);
+    }
+
+    #[tokio::test]
+    async fn multiple_links_on_one_connection() {
+        let mut device = MockDevice::new(8, 8);
+        device.output = b"0123".to_vec();
+        let conn = Connection::from_client(MockClient::new(device));
+
+        let mut inst = conn.create_link("inst0").await.unwrap();
+        let mut gpib = conn.create_link("gpib0,5").await.unwrap();
+        gpib.device_write(b"*IDN?".to_vec()).await.unwrap();
+        let data = inst.device_read().await.unwrap();
+
+        assert_eq!(inst.link_id(), 1);
+        assert_eq!(gpib.link_id(), 2);
+        assert_eq!(gpib.device(), "gpib0,5");
+        assert_eq!(data, b"0123");
+        assert_eq!(conn.client().device().received, b"*IDN?");
+    }
+
+    #[tokio::test]
+    async fn create_locked_link() {
+        let conn = Connection::from_client(MockClient::new(MockDevice::new(8, 8)));
+
+        conn.create_link("inst0").await.unwrap();
+        let options = LinkOptions {
+            lock: true,
+            lock_timeout: Duration::from_millis(500),
+        };
+        let link = conn.create_link_with("inst1", options).await.unwrap();
+
+        assert_eq!(link.device(), "inst1");
+        assert_eq!(conn.client().device().link_locks, [(false, 0), (true, 500)]);
+    }
+
+    #[tokio::test]
+    async fn device_readstb() {
+        let mut device = MockDevice::new(8, 8);
+        device.stb = 0x42;
+        let mut client = CoreClient::from_client(MockClient::new(device), DEFAULT_DEVICE)
+            .await
+            .unwrap();
+
+        assert_eq!(client.device_readstb().await.unwrap(), 0x42);
     }
 }
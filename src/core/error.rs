@@ -0,0 +1,111 @@
+//! Error codes returned by VXI-11 servers.
+
+use std::fmt;
+
+/// Error code reported in the `error` field of a VXI-11 response, as defined in
+/// section B.5.2 of the VXI-11 specification.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum VxiErrorCode {
+    SyntaxError,
+    DeviceNotAccessible,
+    InvalidLinkIdentifier,
+    ParameterError,
+    ChannelNotEstablished,
+    OperationNotSupported,
+    OutOfResources,
+    DeviceLockedByAnotherLink,
+    NoLockHeldByThisLink,
+    IoTimeout,
+    IoError,
+    InvalidAddress,
+    Abort,
+    ChannelAlreadyEstablished,
+    /// An error code not defined by the specification.
+    Unknown(u32),
+}
+
+impl VxiErrorCode {
+    /// The numeric code as transmitted on the wire.
+    pub fn code(self) -> u32 {
+        match self {
+            VxiErrorCode::SyntaxError => 1,
+            VxiErrorCode::DeviceNotAccessible => 3,
+            VxiErrorCode::InvalidLinkIdentifier => 4,
+            VxiErrorCode::ParameterError => 5,
+            VxiErrorCode::ChannelNotEstablished => 6,
+            VxiErrorCode::OperationNotSupported => 8,
+            VxiErrorCode::OutOfResources => 9,
+            VxiErrorCode::DeviceLockedByAnotherLink => 11,
+            VxiErrorCode::NoLockHeldByThisLink => 12,
+            VxiErrorCode::IoTimeout => 15,
+            VxiErrorCode::IoError => 17,
+            VxiErrorCode::InvalidAddress => 21,
+            VxiErrorCode::Abort => 23,
+            VxiErrorCode::ChannelAlreadyEstablished => 29,
+            VxiErrorCode::Unknown(code) => code,
+        }
+    }
+
+    /// A short name of the error, e.g. for use as metrics label.
+    pub fn name(self) -> &'static str {
+        match self {
+            VxiErrorCode::SyntaxError => "syntax_error",
+            VxiErrorCode::DeviceNotAccessible => "device_not_accessible",
+            VxiErrorCode::InvalidLinkIdentifier => "invalid_link_identifier",
+            VxiErrorCode::ParameterError => "parameter_error",
+            VxiErrorCode::ChannelNotEstablished => "channel_not_established",
+            VxiErrorCode::OperationNotSupported => "operation_not_supported",
+            VxiErrorCode::OutOfResources => "out_of_resources",
+            VxiErrorCode::DeviceLockedByAnotherLink => "device_locked_by_another_link",
+            VxiErrorCode::NoLockHeldByThisLink => "no_lock_held_by_this_link",
+            VxiErrorCode::IoTimeout => "io_timeout",
+            VxiErrorCode::IoError => "io_error",
+            VxiErrorCode::InvalidAddress => "invalid_address",
+            VxiErrorCode::Abort => "abort",
+            VxiErrorCode::ChannelAlreadyEstablished => "channel_already_established",
+            VxiErrorCode::Unknown(_) => "unknown",
+        }
+    }
+}
+
+impl From<u32> for VxiErrorCode {
+    fn from(code: u32) -> Self {
+        match code {
+            1 => VxiErrorCode::SyntaxError,
+            3 => VxiErrorCode::DeviceNotAccessible,
+            4 => VxiErrorCode::InvalidLinkIdentifier,
+            5 => VxiErrorCode::ParameterError,
+            6 => VxiErrorCode::ChannelNotEstablished,
+            8 => VxiErrorCode::OperationNotSupported,
+            9 => VxiErrorCode::OutOfResources,
+            11 => VxiErrorCode::DeviceLockedByAnotherLink,
+            12 => VxiErrorCode::NoLockHeldByThisLink,
+            15 => VxiErrorCode::IoTimeout,
+            17 => VxiErrorCode::IoError,
+            21 => VxiErrorCode::InvalidAddress,
+            23 => VxiErrorCode::Abort,
+            29 => VxiErrorCode::ChannelAlreadyEstablished,
+            code => VxiErrorCode::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for VxiErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name(), self.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_roundtrip() {
+        for code in 0..32 {
+            assert_eq!(VxiErrorCode::from(code).code(), code);
+        }
+        assert_eq!(VxiErrorCode::from(15), VxiErrorCode::IoTimeout);
+        assert_eq!(VxiErrorCode::from(2), VxiErrorCode::Unknown(2));
+    }
+}
@@ -0,0 +1,330 @@
+//! Standard GPIB commands for VXI-11 gateways, as specified in VXI-11.2.
+//!
+//! The commands are executed with `device_docmd` on a link to the gateway interface
+//! (e.g. `gpib0`). [`GpibGateway`] builds on these to manage the bus and to open links
+//! to the instruments connected to it.
+
+use std::net::IpAddr;
+
+use crate::core::client::{Connection, CoreClient};
+use crate::rpc::{Client, Connect};
+use crate::Error;
+
+pub const CMD_SEND_COMMAND: u32 = 0x020000;
+pub const CMD_BUS_STATUS: u32 = 0x020001;
+pub const CMD_ATN_CONTROL: u32 = 0x020002;
+pub const CMD_REN_CONTROL: u32 = 0x020003;
+pub const CMD_PASS_CONTROL: u32 = 0x020004;
+pub const CMD_BUS_ADDRESS: u32 = 0x02000A;
+pub const CMD_IFC_CONTROL: u32 = 0x020010;
+
+/// Unlisten
+const UNL: u8 = 0x3F;
+/// Untalk
+const UNT: u8 = 0x5F;
+/// Listen address group
+const LAD: u8 = 0x20;
+
+const MAX_ADDRESS: u8 = 30;
+
+/// Status which can be queried with [`CoreClient::gpib_bus_status()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BusStatus {
+    /// 1 if the REN line is asserted
+    Remote = 1,
+    /// 1 if the SRQ line is asserted
+    Srq = 2,
+    /// 1 if the NDAC line is asserted
+    Ndac = 3,
+    /// 1 if the gateway is the system controller
+    SystemController = 4,
+    /// 1 if the gateway is the controller in charge
+    ControllerInCharge = 5,
+    /// 1 if the gateway is addressed to talk
+    Talker = 6,
+    /// 1 if the gateway is addressed to listen
+    Listener = 7,
+    /// The bus address of the gateway
+    BusAddress = 8,
+}
+
+impl<T: Client> CoreClient<T> {
+    /// Send raw GPIB command bytes (e.g. `UNL`, `LAD`) with ATN asserted.
+    pub async fn gpib_send_command(&mut self, cmds: &[u8]) -> crate::Result<()> {
+        self.docmd(CMD_SEND_COMMAND, true, 1, cmds.to_vec())
+            .await
+            .map(|_| ())
+    }
+
+    /// Query the status of the GPIB bus.
+    pub async fn gpib_bus_status(&mut self, status: BusStatus) -> crate::Result<u16> {
+        let data_in = (status as u16).to_be_bytes().to_vec();
+        let data_out = self.docmd(CMD_BUS_STATUS, true, 2, data_in).await?;
+        if data_out.len() < 2 {
+            return Err(Error::CannotUnpack);
+        }
+        Ok(u16::from_be_bytes([data_out[0], data_out[1]]))
+    }
+
+    /// Assert (`true`) or release (`false`) the ATN line.
+    pub async fn gpib_atn_control(&mut self, assert: bool) -> crate::Result<()> {
+        let data_in = (assert as u16).to_be_bytes().to_vec();
+        self.docmd(CMD_ATN_CONTROL, true, 2, data_in)
+            .await
+            .map(|_| ())
+    }
+
+    /// Assert (`true`) or release (`false`) the REN line.
+    pub async fn gpib_ren_control(&mut self, assert: bool) -> crate::Result<()> {
+        let data_in = (assert as u16).to_be_bytes().to_vec();
+        self.docmd(CMD_REN_CONTROL, true, 2, data_in)
+            .await
+            .map(|_| ())
+    }
+
+    /// Pass control to the device at the given primary address.
+    pub async fn gpib_pass_control(&mut self, addr: u32) -> crate::Result<()> {
+        let data_in = addr.to_be_bytes().to_vec();
+        self.docmd(CMD_PASS_CONTROL, true, 4, data_in)
+            .await
+            .map(|_| ())
+    }
+
+    /// Set the primary bus address of the gateway.
+    pub async fn gpib_bus_address(&mut self, addr: u32) -> crate::Result<()> {
+        let data_in = addr.to_be_bytes().to_vec();
+        self.docmd(CMD_BUS_ADDRESS, true, 4, data_in)
+            .await
+            .map(|_| ())
+    }
+
+    /// Pulse the IFC line to reset the bus.
+    pub async fn gpib_ifc_control(&mut self) -> crate::Result<()> {
+        self.docmd(CMD_IFC_CONTROL, true, 0, Vec::new())
+            .await
+            .map(|_| ())
+    }
+}
+
+/// A GPIB interface of a VXI-11 gateway, such as `gpib0` of a Keysight E5810.
+///
+/// The gateway keeps a link to the interface to control the bus and opens separate
+/// links for the instruments with [`GpibGateway::open()`]. All links share a single
+/// connection to the gateway.
+pub struct GpibGateway<T: Client> {
+    conn: Connection<T>,
+    interface: String,
+    link: CoreClient<T>,
+}
+
+impl<T: Connect> GpibGateway<T> {
+    /// Connect to the gateway at `addr` and create a link to `interface`, e.g. `gpib0`.
+    pub async fn connect<A: Into<IpAddr> + Send>(addr: A, interface: &str) -> crate::Result<Self> {
+        let conn = Connection::connect(addr).await?;
+        Self::from_connection(conn, interface).await
+    }
+}
+
+impl<T: Client> GpibGateway<T> {
+    /// Create a link to `interface` on an existing connection to the gateway.
+    pub async fn from_connection(conn: Connection<T>, interface: &str) -> crate::Result<Self> {
+        let link = conn.create_link(interface).await?;
+        Ok(Self {
+            conn,
+            interface: interface.to_string(),
+            link,
+        })
+    }
+
+    /// The link to the interface itself.
+    pub fn link(&mut self) -> &mut CoreClient<T> {
+        &mut self.link
+    }
+
+    /// Reset the bus by pulsing IFC.
+    pub async fn interface_clear(&mut self) -> crate::Result<()> {
+        self.link.gpib_ifc_control().await
+    }
+
+    /// Assert or release REN.
+    pub async fn remote_enable(&mut self, enable: bool) -> crate::Result<()> {
+        self.link.gpib_ren_control(enable).await
+    }
+
+    /// Find the primary addresses of all listeners on the bus.
+    ///
+    /// Each address is addressed to listen and the NDAC line is checked, as a listener
+    /// present on the bus asserts NDAC.
+    pub async fn find_listeners(&mut self) -> crate::Result<Vec<u8>> {
+        let own = self.link.gpib_bus_status(BusStatus::BusAddress).await?;
+        let mut ret = Vec::new();
+        for addr in 0..=MAX_ADDRESS {
+            if addr as u16 == own {
+                continue;
+            }
+            self.link.gpib_send_command(&[UNL, LAD | addr]).await?;
+            self.link.gpib_atn_control(true).await?;
+            if self.link.gpib_bus_status(BusStatus::Ndac).await? != 0 {
+                ret.push(addr);
+            }
+        }
+        self.link.gpib_send_command(&[UNL, UNT]).await?;
+        self.link.gpib_atn_control(false).await?;
+        Ok(ret)
+    }
+
+    /// Open a link to the instrument at the given primary and optional secondary address.
+    pub async fn open(&self, primary: u8, secondary: Option<u8>) -> crate::Result<CoreClient<T>> {
+        let device = self.device_name(primary, secondary)?;
+        self.conn.create_link(&device).await
+    }
+
+    fn device_name(&self, primary: u8, secondary: Option<u8>) -> crate::Result<String> {
+        if primary > MAX_ADDRESS {
+            return Err(Error::InvalidGpibAddress(primary));
+        }
+        match secondary {
+            Some(secondary) if secondary > MAX_ADDRESS => Err(Error::InvalidGpibAddress(secondary)),
+            Some(secondary) => Ok(format!("{},{},{}", self.interface, primary, secondary)),
+            None => Ok(format!("{},{}", self.interface, primary)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    use crate::testing::device::{Docmd, MockClient, MockDevice};
+
+    async fn link(device: MockDevice) -> CoreClient<MockClient> {
+        CoreClient::from_client(MockClient::new(device), "gpib0")
+            .await
+            .unwrap()
+    }
+
+    fn docmd(cmd: u32, data_size: u32, data_in: &[u8]) -> Docmd {
+        Docmd {
+            cmd,
+            network_order: true,
+            data_size,
+            data_in: data_in.to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn bus_commands() {
+        let mut device = MockDevice::new(0, 1024);
+        device.on_docmd = Some(Box::new(|_, _| vec![0x01, 0x02]));
+        let mut link = link(device).await;
+
+        link.gpib_send_command(&[UNL, LAD | 5]).await.unwrap();
+        assert_eq!(link.gpib_bus_status(BusStatus::Ndac).await.unwrap(), 0x0102);
+        link.gpib_atn_control(true).await.unwrap();
+        link.gpib_ren_control(false).await.unwrap();
+        link.gpib_pass_control(7).await.unwrap();
+        link.gpib_bus_address(21).await.unwrap();
+        link.gpib_ifc_control().await.unwrap();
+
+        let client = link.connection().client();
+        assert_eq!(
+            client.device().docmds,
+            [
+                docmd(0x020000, 1, &[0x3F, 0x25]),
+                docmd(0x020001, 2, &[0, 3]),
+                docmd(0x020002, 2, &[0, 1]),
+                docmd(0x020003, 2, &[0, 0]),
+                docmd(0x020004, 4, &[0, 0, 0, 7]),
+                docmd(0x02000A, 4, &[0, 0, 0, 21]),
+                docmd(0x020010, 0, &[]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn short_bus_status() {
+        let mut device = MockDevice::new(0, 1024);
+        device.on_docmd = Some(Box::new(|_, _| vec![1]));
+        let mut link = link(device).await;
+
+        let ret = link.gpib_bus_status(BusStatus::Remote).await;
+        assert!(matches!(ret, Err(Error::CannotUnpack)));
+    }
+
+    /// A bus with the gateway at address 3 and listeners at 5 and 12.
+    fn bus() -> MockDevice {
+        let addressed = Arc::new(Mutex::new(None));
+        let mut device = MockDevice::new(0, 1024);
+        device.on_docmd = Some(Box::new(move |cmd, data_in| {
+            let mut addressed = addressed.lock().unwrap();
+            let status: u16 = match (cmd, data_in) {
+                (CMD_SEND_COMMAND, [UNL, lad]) => {
+                    *addressed = Some(lad & !LAD);
+                    0
+                }
+                (CMD_SEND_COMMAND, _) => {
+                    *addressed = None;
+                    0
+                }
+                (CMD_BUS_STATUS, [0, 8]) => 3,
+                (CMD_BUS_STATUS, [0, 3]) => matches!(*addressed, Some(5) | Some(12)) as u16,
+                _ => 0,
+            };
+            status.to_be_bytes().to_vec()
+        }));
+        device
+    }
+
+    #[tokio::test]
+    async fn find_listeners() {
+        let conn = Connection::from_client(MockClient::new(bus()));
+        let mut gateway = GpibGateway::from_connection(conn, "gpib0").await.unwrap();
+
+        assert_eq!(gateway.find_listeners().await.unwrap(), [5, 12]);
+
+        let device = gateway.link().connection().client().device();
+        assert_eq!(device.docmds[0], docmd(CMD_BUS_STATUS, 2, &[0, 8]));
+        // every address but the own one is addressed to listen and NDAC is checked
+        assert_eq!(device.docmds.len(), 1 + 30 * 3 + 2);
+        assert_eq!(
+            device.docmds[1..4],
+            [
+                docmd(CMD_SEND_COMMAND, 1, &[UNL, LAD]),
+                docmd(CMD_ATN_CONTROL, 2, &[0, 1]),
+                docmd(CMD_BUS_STATUS, 2, &[0, 3]),
+            ]
+        );
+        assert!(!device
+            .docmds
+            .contains(&docmd(CMD_SEND_COMMAND, 1, &[UNL, LAD | 3])));
+        assert_eq!(
+            device.docmds[device.docmds.len() - 2..],
+            [
+                docmd(CMD_SEND_COMMAND, 1, &[UNL, UNT]),
+                docmd(CMD_ATN_CONTROL, 2, &[0, 0]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn open_instruments() {
+        let conn = Connection::from_client(MockClient::new(MockDevice::new(0, 1024)));
+        let mut gateway = GpibGateway::from_connection(conn, "gpib0").await.unwrap();
+
+        let first = gateway.open(5, None).await.unwrap();
+        let second = gateway.open(7, Some(2)).await.unwrap();
+        assert_eq!((first.device(), second.device()), ("gpib0,5", "gpib0,7,2"));
+        assert!(matches!(
+            gateway.open(31, None).await,
+            Err(Error::InvalidGpibAddress(31))
+        ));
+        assert!(matches!(
+            gateway.open(5, Some(31)).await,
+            Err(Error::InvalidGpibAddress(31))
+        ));
+
+        let device = gateway.link().connection().client().device();
+        assert_eq!(device.devices, ["gpib0", "gpib0,5", "gpib0,7,2"]);
+    }
+}
@@ -1,2 +1,5 @@
-mod calls;
+pub(crate) mod calls;
 pub mod client;
+pub mod error;
+pub mod gpib;
+pub mod shared;
@@ -0,0 +1,168 @@
+//! A [`CoreClient`] handle which can be shared between tasks.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use futures::lock::{Mutex, MutexGuard};
+
+use crate::core::client::{CoreClient, ReadOptions, ReadReason, WriteOptions};
+use crate::rpc::Client;
+
+/// A cloneable handle to a [`CoreClient`] which may be used by several tasks.
+///
+/// Operations are serialized: each call locks the link for its whole duration, such that
+/// e.g. a chunked write is never interleaved with another write. Use
+/// [`SharedCoreClient::transaction()`] or [`SharedCoreClient::lock()`] to perform several
+/// operations without interruption, e.g. to write a query and read back its response.
+pub struct SharedCoreClient<T: Client> {
+    inner: Arc<Mutex<CoreClient<T>>>,
+}
+
+impl<T: Client> Clone for SharedCoreClient<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: Client> From<CoreClient<T>> for SharedCoreClient<T> {
+    fn from(client: CoreClient<T>) -> Self {
+        Self::new(client)
+    }
+}
+
+impl<T: Client> SharedCoreClient<T> {
+    pub fn new(client: CoreClient<T>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(client)),
+        }
+    }
+
+    /// Acquire exclusive access to the link. Other tasks are blocked until the returned
+    /// guard is dropped.
+    pub async fn lock(&self) -> MutexGuard<'_, CoreClient<T>> {
+        self.inner.lock().await
+    }
+
+    /// Run `f` with exclusive access to the link. No operations of other tasks are
+    /// interleaved with the ones performed by `f`.
+    ///
+    /// ```ignore
+    /// let idn = client
+    ///     .transaction(|c| Box::pin(async move {
+    ///         c.device_write(b"*IDN?\n".to_vec()).await?;
+    ///         c.device_read().await
+    ///     }))
+    ///     .await?;
+    /// ```
+    pub async fn transaction<F, R>(&self, f: F) -> crate::Result<R>
+    where
+        F: for<'a> FnOnce(&'a mut CoreClient<T>) -> BoxFuture<'a, crate::Result<R>>,
+    {
+        let mut client = self.inner.lock().await;
+        f(&mut client).await
+    }
+
+    pub async fn device_write(&self, data: Vec<u8>) -> crate::Result<usize> {
+        self.inner.lock().await.device_write(data).await
+    }
+
+    pub async fn device_write_with(
+        &self,
+        data: Vec<u8>,
+        options: WriteOptions,
+    ) -> crate::Result<usize> {
+        self.inner
+            .lock()
+            .await
+            .device_write_with(data, options)
+            .await
+    }
+
+    pub async fn device_read(&self) -> crate::Result<Vec<u8>> {
+        self.inner.lock().await.device_read().await
+    }
+
+    pub async fn device_read_with(
+        &self,
+        options: ReadOptions,
+    ) -> crate::Result<(Vec<u8>, ReadReason)> {
+        self.inner.lock().await.device_read_with(options).await
+    }
+
+    /// Refer to [`CoreClient::device_read_bytes()`].
+    pub async fn device_read_bytes(
+        &self,
+        options: ReadOptions,
+    ) -> crate::Result<(Bytes, ReadReason)> {
+        self.inner.lock().await.device_read_bytes(options).await
+    }
+
+    pub async fn device_readstb(&self) -> crate::Result<u8> {
+        self.inner.lock().await.device_readstb().await
+    }
+
+    pub async fn device_clear(&self) -> crate::Result<()> {
+        self.inner.lock().await.device_clear().await
+    }
+
+    pub async fn docmd(
+        &self,
+        cmd: u32,
+        network_order: bool,
+        data_size: u32,
+        data_in: Vec<u8>,
+    ) -> crate::Result<Vec<u8>> {
+        self.inner
+            .lock()
+            .await
+            .docmd(cmd, network_order, data_size, data_in)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::client::DEFAULT_DEVICE;
+    use crate::testing::device::{MockClient, MockDevice};
+
+    #[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    #[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+    fn shared_client_is_send_sync() {
+        #[cfg(feature = "tokio")]
+        assert_send_sync::<SharedCoreClient<crate::tokio::TcpClient>>();
+        #[cfg(feature = "async-std")]
+        assert_send_sync::<SharedCoreClient<crate::async_std::TcpClient>>();
+        #[cfg(feature = "smol")]
+        assert_send_sync::<SharedCoreClient<crate::smol::TcpClient>>();
+    }
+
+    #[tokio::test]
+    async fn transactions_are_not_interleaved() {
+        let mock = MockClient::new(MockDevice::new(8, 8));
+        let client = CoreClient::from_client(mock.clone(), DEFAULT_DEVICE)
+            .await
+            .unwrap();
+        let shared = SharedCoreClient::new(client);
+        let other = shared.clone();
+
+        let transaction = shared.transaction(|c| {
+            Box::pin(async move {
+                c.device_write(b"A".to_vec()).await?;
+                tokio::task::yield_now().await;
+                c.device_write(b"B".to_vec()).await
+            })
+        });
+        let (first, second) = tokio::join!(transaction, other.device_write(b"C".to_vec()));
+        first.unwrap();
+        second.unwrap();
+
+        assert_eq!(mock.device().received, b"ABC");
+    }
+}
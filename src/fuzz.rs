@@ -0,0 +1,40 @@
+//! Entry points of the fuzz targets in `fuzz/`. Only built with `--cfg fuzzing`, which is
+//! set by `cargo fuzz`.
+
+use bytes::Bytes;
+use futures::executor::block_on;
+
+use crate::core::calls::{
+    CreateLinkResponse, DeviceDocmdResponse, DeviceReadResponse, DeviceReadStbResponse,
+    DeviceWriteResponse,
+};
+use crate::portmapper::Mapping;
+use crate::record::{self, RecordLimits};
+use crate::rpc::{self, Deserialize};
+
+/// Decode `data` as the result of every call of the VXI-11 core channel, as the port of
+/// `GETPORT` and a port mapper [`Mapping`], and as RPC reply message.
+pub fn decode_replies(data: &[u8]) {
+    let _ = CreateLinkResponse::deserialize(data);
+    let _ = DeviceWriteResponse::deserialize(data);
+    let _ = DeviceReadResponse::deserialize(data);
+    let _ = DeviceReadResponse::deserialize_bytes(&Bytes::copy_from_slice(data));
+    let _ = DeviceDocmdResponse::deserialize(data);
+    let _ = DeviceReadStbResponse::deserialize(data);
+    let _ = u32::deserialize(data);
+    let _ = Mapping::deserialize(data);
+    let _ = rpc::decode_reply(data);
+}
+
+/// Read records from `data` until it is exhausted or invalid.
+pub fn read_records(data: &[u8]) {
+    let limits = RecordLimits {
+        max_record_size: 64 * 1024,
+        max_fragment_size: 16 * 1024,
+        send_fragment_size: 16 * 1024,
+    };
+    let mut data = data;
+    while let Ok(record) = block_on(record::read_record(&mut data, &limits)) {
+        let _ = rpc::decode_reply(&record);
+    }
+}
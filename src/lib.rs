@@ -1,32 +1,61 @@
 //! This crate provides an asynchronous *client* implementation for the the VXI-11 protcol.
 //!
-//! The transport layer is represented in the [`rpc::Client`] trait and implemented both for
-//! `tokio` (with [`tokio::TcpClient`]) and `async-std` (with [`async_std::TcpClient`]).
-//! The `tokio` (default) and the `async-std` features enable the conditional compilation of above modules.
-//! It is also possible to deactive both features and to provide a custom [`rpc::Client`] implementation.
+//! The transport layer is represented in the [`rpc::Client`] trait. [`stream::StreamClient`]
+//! implements it over any stream implementing the `futures-io` traits and
+//! [`stream::TcpClient`] over TCP with a [`stream::Connector`] of an async runtime. Connectors for
+//! `tokio` (with [`tokio::TcpClient`]), `async-std` (with [`async_std::TcpClient`]) and `smol`
+//! (with [`smol::TcpClient`]) are provided.
+//! The `tokio` (default), `async-std` and `smol` features enable the conditional compilation of above modules.
+//! The `blocking` feature adds a synchronous client in [`blocking`] which does not require
+//! an async runtime.
+//! The `tracing` feature emits a `tracing` span for every [`CoreClient`] operation and
+//! every RPC call. The `metrics` feature records operation counters and latency histograms
+//! with the `metrics` crate. The `derive` feature exports the derive macros for the XDR
+//! traits [`xdr::Serialize`] and [`xdr::Deserialize`], which the crate uses for its own
+//! calls.
+//! It is also possible to deactive all features and to provide a custom stream or [`rpc::Client`] implementation.
 //!
 //! Main rpc client is implemented with the [`CoreClient<T: Client>`][core::client::CoreClient]
+//! Several links may share one [`Connection`][core::client::Connection] to a server.
+//! [`sim::SimClient`] serves the calls with a simulated instrument instead, e.g. a SCPI
+//! instrument built with [`scpi::Router`].
 //!
 //! VXI-11 is a somewhat old and exotic protocol. It's a stack of a few technologies (specs linked):
 //!  
-//!  - [XDR](https://tools.ietf.org/html/rfc4506) - a very simple serialization format
+//!  - [XDR](https://tools.ietf.org/html/rfc4506) - a very simple serialization format, implemented in [`xdr`]
 //!  - [ONC-RPC](https://tools.ietf.org/html/rfc5531#section-9) - Also known as [SUN RPC](https://en.wikipedia.org/wiki/Sun_RPC). It uses XDR.
 //!  - The [port mapper](https://tools.ietf.org/html/rfc1833) protocol is a protcol on top of ONC-RPC used to establish
-//!     a connection to a server. A client first ask the sever over this protocol to which
-//!     port it should connect. The port of the portmapper protocol is standardized to 111.
+//!    a connection to a server. A client first ask the sever over this protocol to which
+//!    port it should connect. The port of the portmapper protocol is standardized to 111.
 //!  - [VXI-11](https://www.vxibus.org/specifications.html) uses the port mapper protocol to connect a client to a server and adds additional RPC calls.
-//!     However, most communication still behaves as a byte stream using a write and a read RPC.
+//!    However, most communication still behaves as a byte stream using a write and a read RPC.
 //!
+// allows the derive macros to refer to this crate as `::async_vxi11` from within
+extern crate self as async_vxi11;
+
 use std::io;
 
 use thiserror::Error;
 
-pub use crate::core::client::{CoreClient, VxiOptions};
-pub use rpc::{Client, Deserialize, Serialize};
+pub use crate::auth::Credentials;
+pub use crate::core::client::{
+    Connection, CoreClient, Link, LinkOptions, ReadOptions, ReadReason, VxiOptions, WriteOptions,
+};
+pub use crate::core::error::VxiErrorCode;
+pub use crate::core::gpib::GpibGateway;
+pub use crate::core::shared::SharedCoreClient;
+pub use rpc::{Client, Connect, Deserialize, RpcClient, Serialize};
 
+pub mod auth;
+pub mod capture;
 pub mod core;
 pub mod portmapper;
+pub mod record;
 pub mod rpc;
+pub mod scpi;
+pub mod sim;
+pub mod stream;
+pub mod xdr;
 
 #[cfg(feature = "tokio")]
 pub mod tokio;
@@ -34,6 +63,22 @@ pub mod tokio;
 #[cfg(feature = "async-std")]
 pub mod async_std;
 
+#[cfg(feature = "smol")]
+pub mod smol;
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+
+mod stats;
+mod trace;
+
+#[cfg(fuzzing)]
+#[doc(hidden)]
+pub mod fuzz;
+
+#[cfg(test)]
+mod testing;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("IO Error occurred: {0}")]
@@ -46,16 +91,57 @@ pub enum Error {
     UnexpectedXid { expected: u32, actual: u32 },
     #[error("Wrong message type")]
     WrongMessageType,
-    #[error("RPC denied")]
-    RpcDenied,
-    #[error("XDR error")]
-    XdrError(xdr_rs_serialize::error::Error),
+    #[error("XDR error: {0}")]
+    Xdr(xdr::DecodeError),
     #[error("Invalid Port Number")]
     InvalidPortNumber,
     #[error("VXI remote error")]
     VxiRemoteError(u32),
+    #[error("RPC program unavailable")]
+    RpcProgramUnavailable,
+    #[error("RPC program version mismatch, supported versions: {low} to {high}")]
+    RpcProgramMismatch { low: u32, high: u32 },
+    #[error("RPC procedure unavailable")]
+    RpcProcedureUnavailable,
     #[error("Invalid RPC args")]
     RpcInvalidArgs,
+    #[error("RPC system error")]
+    RpcSystemError,
+    #[error("RPC version mismatch, supported versions: {low} to {high}")]
+    RpcVersionMismatch { low: u32, high: u32 },
+    #[error("RPC authentication error: {0:?}")]
+    RpcAuthError(rpc::AuthStat),
+    #[error("Device stopped accepting data after {0} bytes")]
+    IncompleteWrite(usize),
+    #[error("Invalid GPIB address: {0}")]
+    InvalidGpibAddress(u8),
+    #[error("Record of {size} bytes exceeds the limit of {limit} bytes")]
+    RecordTooLarge { size: usize, limit: usize },
+    #[error("Empty fragment within a record")]
+    EmptyFragment,
+    #[error("Invalid or unsupported capture file")]
+    InvalidCapture,
+    #[error("Call {0} does not match the capture")]
+    ReplayMismatch(usize),
+    #[error("Connection closed")]
+    ConnectionClosed,
+    #[error("Invalid SCPI response: {0:?}")]
+    InvalidScpiResponse(String),
+    #[error("SCPI command failed, ESR {esr:#04x}, errors {errors:?}")]
+    ScpiCommandFailed {
+        esr: u8,
+        errors: Vec<scpi::ScpiError>,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// The error code if the server reported an error.
+    pub fn vxi_error_code(&self) -> Option<VxiErrorCode> {
+        match self {
+            Error::VxiRemoteError(code) => Some(VxiErrorCode::from(*code)),
+            _ => None,
+        }
+    }
+}
@@ -1,30 +1,40 @@
-use xdr_rs_serialize::ser::XDROut;
+use std::time::Instant;
 
-use crate::rpc;
-use crate::rpc::{Client, Serialize};
+use async_vxi11_derive::{XdrDeserialize, XdrSerialize};
 
-const PROG: u32 = 100000;
-const VERS: u32 = 2;
+use crate::rpc::{Client, Procedure, RpcClient};
+use crate::stats;
 
-const PROC_GETPORT: u32 = 3;
+/// RPC program number of the port mapper.
+pub const PROG: u32 = 100000;
+/// RPC program version of the port mapper.
+pub const VERS: u32 = 2;
+/// Well-known port of the port mapper.
+pub const PORT: u16 = 111;
+
+/// Look up the port of a program. The result is 0 if the program is not registered.
+pub const GETPORT: Procedure<Mapping, u32> = Procedure::new(PROG, VERS, 3);
 
 const IPPROTO_TCP: u32 = 6;
 
-struct Mapping {
-    prog: u32,
-    vers: u32,
-    prot: u32,
-    port: u32,
+/// A program registered with the port mapper.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, XdrSerialize, XdrDeserialize)]
+pub struct Mapping {
+    pub prog: u32,
+    pub vers: u32,
+    pub prot: u32,
+    pub port: u32,
 }
 
-impl Serialize for Mapping {
-    fn serialize(&self, out: &mut Vec<u8>) {
-        // actually cannot fail, because write_xdr call vec::write
-        // which does not fail
-        u32::write_xdr(&self.prog, out).unwrap();
-        u32::write_xdr(&self.vers, out).unwrap();
-        u32::write_xdr(&self.prot, out).unwrap();
-        u32::write_xdr(&self.port, out).unwrap();
+impl Mapping {
+    /// The mapping of `prog` and `vers` over TCP, with the port to be looked up.
+    pub fn tcp(prog: u32, vers: u32) -> Self {
+        Self {
+            prog,
+            vers,
+            prot: IPPROTO_TCP,
+            port: 0,
+        }
     }
 }
 
@@ -36,15 +46,12 @@ impl Serialize for Mapping {
 /// The client usually proceeds to connect to establish a connection to the returned port.
 ///
 /// The port mapper RPC call is specified in [IETF RFC 1833](https://tools.ietf.org/html/rfc1833)
-pub async fn get_port<C: Client>(client: &mut C, prog: u32, vers: u32) -> crate::Result<u16> {
-    let request = Mapping {
-        prog,
-        vers,
-        prot: IPPROTO_TCP,
-        port: 0,
-    };
-
-    let ret: u32 = rpc::call(client, &request, PROG, VERS, PROC_GETPORT).await?;
+pub async fn get_port<C: Client>(client: &C, prog: u32, vers: u32) -> crate::Result<u16> {
+    let start = Instant::now();
+    let ret = RpcClient::new(client.clone())
+        .call(&GETPORT, &Mapping::tcp(prog, vers))
+        .await?;
+    stats::portmapper_lookup(client, start);
 
     if ret > 65535 {
         return Err(crate::Error::InvalidPortNumber);
@@ -0,0 +1,218 @@
+//! Record marking of RPC messages over stream transports ([RFC 5531, section 11]).
+//!
+//! A record consists of one or more fragments, each preceded by a 4 byte header with the
+//! length of the fragment and a flag marking the last fragment of the record.
+//!
+//! [RFC 5531, section 11]: https://tools.ietf.org/html/rfc5531#section-11
+
+use byteorder::{BigEndian, ByteOrder};
+use bytes::{Bytes, BytesMut};
+use futures::io::{AsyncRead, AsyncReadExt};
+
+use crate::Error;
+
+const LAST_FRAGMENT: u32 = 0x80000000;
+
+/// Size limits of records, protecting against peers announcing huge records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecordLimits {
+    /// Maximum size of a received record, without the fragment headers.
+    pub max_record_size: usize,
+    /// Maximum size of a received fragment.
+    pub max_fragment_size: usize,
+    /// Outgoing records are split into fragments of at most this size.
+    pub send_fragment_size: usize,
+}
+
+impl Default for RecordLimits {
+    fn default() -> Self {
+        Self {
+            max_record_size: 16 * 1024 * 1024,
+            max_fragment_size: 4 * 1024 * 1024,
+            send_fragment_size: 4 * 1024 * 1024,
+        }
+    }
+}
+
+/// Split a serialized message, starting with a single record marking header, into
+/// fragments of at most `limits.send_fragment_size` bytes.
+pub(crate) fn fragment(msg: Vec<u8>, limits: &RecordLimits) -> Vec<u8> {
+    let size = limits.send_fragment_size.max(1);
+    if msg.len() - 4 <= size {
+        return msg;
+    }
+    let body = &msg[4..];
+    let num = body.len().div_ceil(size);
+    let mut ret = Vec::with_capacity(body.len() + 4 * num);
+    for (i, chunk) in body.chunks(size).enumerate() {
+        let mut header = chunk.len() as u32;
+        if i + 1 == num {
+            header |= LAST_FRAGMENT;
+        }
+        ret.extend_from_slice(&header.to_be_bytes());
+        ret.extend_from_slice(chunk);
+    }
+    ret
+}
+
+/// Reassembles the fragments of a record into a buffer with a single record marking
+/// header, as expected by [`onc_rpc::RpcMessage::from_bytes()`].
+struct Reassembly<'a> {
+    buf: BytesMut,
+    limits: &'a RecordLimits,
+}
+
+impl<'a> Reassembly<'a> {
+    fn new(limits: &'a RecordLimits) -> Self {
+        let mut buf = BytesMut::new();
+        buf.resize(4, 0);
+        Self { buf, limits }
+    }
+
+    /// Check the fragment `header` and make room for its data. Returns the buffer to read
+    /// the fragment into and whether it is the last fragment.
+    ///
+    /// Empty fragments are only accepted at the end of a record, as a peer could otherwise
+    /// keep the reader busy forever without sending any data.
+    fn fragment(&mut self, header: [u8; 4]) -> crate::Result<(&mut [u8], bool)> {
+        let header = BigEndian::read_u32(&header);
+        let len = (header & !LAST_FRAGMENT) as usize;
+        let last = header & LAST_FRAGMENT != 0;
+        if len == 0 && !last {
+            return Err(Error::EmptyFragment);
+        }
+        if len > self.limits.max_fragment_size {
+            return Err(Error::RecordTooLarge {
+                size: len,
+                limit: self.limits.max_fragment_size,
+            });
+        }
+        let start = self.buf.len();
+        let size = start - 4 + len;
+        if size > self.limits.max_record_size {
+            return Err(Error::RecordTooLarge {
+                size,
+                limit: self.limits.max_record_size,
+            });
+        }
+        self.buf.resize(start + len, 0);
+        Ok((&mut self.buf[start..], last))
+    }
+
+    fn finish(mut self) -> Bytes {
+        let len = self.buf.len() as u32 - 4;
+        BigEndian::write_u32(&mut self.buf[0..4], LAST_FRAGMENT | len);
+        self.buf.freeze()
+    }
+}
+
+/// Read a record from `sock`.
+pub(crate) async fn read_record<T: AsyncRead + Unpin>(
+    sock: &mut T,
+    limits: &RecordLimits,
+) -> crate::Result<Bytes> {
+    let mut record = Reassembly::new(limits);
+    loop {
+        let mut header = [0_u8; 4];
+        sock.read_exact(&mut header).await.map_err(Error::Io)?;
+        let (buf, last) = record.fragment(header)?;
+        sock.read_exact(buf).await.map_err(Error::Io)?;
+        if last {
+            return Ok(record.finish());
+        }
+    }
+}
+
+/// Read a record from the blocking `sock`.
+#[cfg(feature = "blocking")]
+pub(crate) fn read_record_blocking<T: std::io::Read>(
+    sock: &mut T,
+    limits: &RecordLimits,
+) -> crate::Result<Bytes> {
+    let mut record = Reassembly::new(limits);
+    loop {
+        let mut header = [0_u8; 4];
+        sock.read_exact(&mut header).map_err(Error::Io)?;
+        let (buf, last) = record.fragment(header)?;
+        sock.read_exact(buf).map_err(Error::Io)?;
+        if last {
+            return Ok(record.finish());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+
+    fn limits(max_record_size: usize, max_fragment_size: usize) -> RecordLimits {
+        RecordLimits {
+            max_record_size,
+            max_fragment_size,
+            send_fragment_size: max_fragment_size,
+        }
+    }
+
+    #[test]
+    fn fragment_and_reassemble() {
+        let msg = vec![0x80, 0, 0, 5, 1, 2, 3, 4, 5];
+
+        let data = fragment(msg.clone(), &limits(16, 2));
+
+        assert_eq!(data, [0, 0, 0, 2, 1, 2, 0, 0, 0, 2, 3, 4, 0x80, 0, 0, 1, 5]);
+        let record = block_on(read_record(&mut &data[..], &limits(16, 2))).unwrap();
+        assert_eq!(record, msg);
+    }
+
+    #[test]
+    fn send_fragment_size() {
+        let msg = vec![0x80, 0, 0, 5, 1, 2, 3, 4, 5];
+        let limits = RecordLimits {
+            send_fragment_size: 3,
+            ..limits(16, 16)
+        };
+
+        let data = fragment(msg, &limits);
+
+        assert_eq!(data, [0, 0, 0, 3, 1, 2, 3, 0x80, 0, 0, 2, 4, 5]);
+    }
+
+    #[test]
+    fn small_records_are_not_fragmented() {
+        let msg = vec![0x80, 0, 0, 2, 1, 2];
+        assert_eq!(fragment(msg.clone(), &Default::default()), msg);
+    }
+
+    #[test]
+    fn oversized_fragment() {
+        let data = [0x80, 0, 0, 5, 1, 2, 3, 4, 5];
+        let ret = block_on(read_record(&mut &data[..], &limits(16, 4)));
+        assert!(matches!(
+            ret,
+            Err(Error::RecordTooLarge { size: 5, limit: 4 })
+        ));
+    }
+
+    #[test]
+    fn oversized_record() {
+        let data = [0, 0, 0, 2, 1, 2, 0x80, 0, 0, 2, 3, 4];
+        let ret = block_on(read_record(&mut &data[..], &limits(3, 4)));
+        assert!(matches!(
+            ret,
+            Err(Error::RecordTooLarge { size: 4, limit: 3 })
+        ));
+    }
+
+    #[test]
+    fn empty_fragments() {
+        let data = [0, 0, 0, 0, 0, 0, 0, 0, 0x80, 0, 0, 1, 1];
+        let ret = block_on(read_record(&mut &data[..], &limits(16, 4)));
+        assert!(matches!(ret, Err(Error::EmptyFragment)));
+
+        let data = [0, 0, 0, 1, 1, 0x80, 0, 0, 0];
+        let record = block_on(read_record(&mut &data[..], &limits(16, 4))).unwrap();
+        assert_eq!(record, [0x80, 0, 0, 1, 1][..]);
+    }
+}
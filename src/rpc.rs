@@ -1,95 +1,524 @@
-use std::net::IpAddr;
+//! ONC-RPC client.
+//!
+//! [`RpcClient`] performs calls of any RPC program over a [`Client`] transport. The
+//! procedures of a program are declared as [`Procedure`] constants with their argument
+//! and result types, e.g. for the `GETPORT` procedure of the port mapper:
+//!
+//! ```ignore
+//! const GETPORT: Procedure<Mapping, u32> = Procedure::new(100000, 2, 3);
+//!
+//! let port = rpc.call(&GETPORT, &mapping).await?;
+//! ```
+//!
+//! The VXI-11 core channel ([`crate::Connection`]) and the port mapper
+//! ([`crate::portmapper`]) are built on top of it.
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use onc_rpc::auth::AuthFlavor;
-use onc_rpc::CallBody;
-use xdr_rs_serialize::de::XDRIn;
-use xdr_rs_serialize::ser::XDROut;
+use onc_rpc::{
+    AcceptedStatus, AuthError, CallBody, MessageType, RejectedReply, ReplyBody, RpcMessage,
+};
+
+use crate::auth::{Auth, Credentials};
+use crate::trace::{self, rpc_span};
+use crate::xdr::Decoder;
+use crate::Error;
 
 pub type Request = CallBody<Vec<u8>, Vec<u8>>;
 
 /// Trait defining the transport layer over which the VXI-11 protocol runs.
 /// This trait uses `#[async_trait]` - Its "actual" signature is as follows:
 ///
-/// ```
+/// ```ignore
 /// #[async_trait]
-/// pub trait Client: Sized {
-///     async fn connect_with_mapper<T: Into<IpAddr> + Send>(
-///        addr: T,
-///        prog: u32,
-///        vers: u32,
-///    ) -> crate::Result<Self>;
-///    async fn call(&mut self, body: Request) -> crate::Result<Bytes>;
+/// pub trait Client: Clone + Send + Sync + Sized {
+///    async fn call(&self, body: Request) -> crate::Result<Bytes>;
+///    fn peer_addr(&self) -> Option<SocketAddr> { None }
 ///}
 /// ```
+///
+/// A client is a handle to a connection. Clones of it share the same connection and
+/// may perform calls concurrently. The transport is responsible for assigning
+/// transaction ids and for matching replies to their calls.
 #[async_trait]
-pub trait Client: Sized {
+pub trait Client: Clone + Send + Sync + Sized {
+    /// Perform an RPC call and return the reply message, including its record marking
+    /// header. Use [`decode_reply()`] to extract the result.
+    async fn call(&self, body: Request) -> crate::Result<Bytes>;
+
+    /// The address of the server, if known. Used to annotate traces.
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+}
+
+/// Transports which can establish a connection on their own, using the port mapper
+/// protocol to find the port of the server.
+#[async_trait]
+pub trait Connect: Client {
     /// Connect the the server with the portmapper protocol.
     async fn connect_with_mapper<T: Into<IpAddr> + Send>(
         addr: T,
         prog: u32,
         vers: u32,
     ) -> crate::Result<Self>;
+}
+
+pub use crate::xdr::{Deserialize, Serialize};
+
+#[cfg(feature = "derive")]
+pub use crate::xdr::{XdrDeserialize, XdrSerialize};
+
+/// Perform an RPC call with the given client and the given request and
+/// reponse types..
+pub async fn call<C: Client, Req: Serialize, Resp: Deserialize>(
+    client: &C,
+    req: &Req,
+    prog: u32,
+    vers: u32,
+    call: u32,
+) -> crate::Result<Resp> {
+    call_with_auth(client, &Auth::default(), req, prog, vers, call).await
+}
+
+/// Perform an RPC call like [`call()`], authenticated with `auth`.
+pub async fn call_with_auth<C: Client, Req: Serialize, Resp: Deserialize>(
+    client: &C,
+    auth: &Auth,
+    req: &Req,
+    prog: u32,
+    vers: u32,
+    call: u32,
+) -> crate::Result<Resp> {
+    let mut payload = Vec::new();
+    req.serialize(&mut payload);
+    log::debug!("Initiating call from prog={}, call={}", prog, call);
+    let span = rpc_span!(prog, vers, call);
+    let start = Instant::now();
+    trace::instrument(&span, async {
+        // keep the payload for a retry in case the server rejects the short hand credentials
+        let retry = auth.uses_short().then(|| payload.clone());
+        let mut reply = client.call(auth.request(prog, vers, call, payload)).await?;
+        let mut ret = decode_reply_with(&reply, auth).map(|data| reply.slice_ref(data));
+        if let Some(payload) = retry {
+            if let Err(Error::RpcAuthError(
+                AuthStat::RejectedCredentials | AuthStat::BadCredentials,
+            )) = ret
+            {
+                auth.reset_short();
+                reply = client.call(auth.request(prog, vers, call, payload)).await?;
+                ret = decode_reply_with(&reply, auth).map(|data| reply.slice_ref(data));
+            }
+        }
+        if let Some(xid) = message_xid(&reply) {
+            span.record("xid", xid);
+        }
+        span.record("latency_us", start.elapsed().as_micros() as u64);
+        log::debug!("Got response with length: {}", reply.len());
+        Resp::deserialize_bytes(&ret?)
+    })
+    .await
+}
+
+/// Arguments and result of procedures without any, e.g. the `NULL` procedure.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Void;
+
+impl Serialize for Void {
+    fn serialize(&self, _out: &mut Vec<u8>) {}
+}
 
-    /// Perform an RPC call and return the reply
-    async fn call(&mut self, body: Request) -> crate::Result<Bytes>;
+impl Deserialize for Void {
+    fn decode(_decoder: &mut Decoder<'_>) -> crate::Result<Self> {
+        Ok(Void)
+    }
 }
 
-/// Trait for XDR serialization. It contains a blanked impl for all types
-/// implementing [`XDROut`]
-pub trait Serialize {
-    fn serialize(&self, out: &mut Vec<u8>);
+/// A remote procedure, identified by program number, version and procedure number,
+/// taking arguments of type `A` and returning a result of type `R`.
+pub struct Procedure<A, R> {
+    prog: u32,
+    vers: u32,
+    procedure: u32,
+    _types: PhantomData<fn(&A) -> R>,
 }
 
-impl<T: XDROut> Serialize for T {
-    fn serialize(&self, out: &mut Vec<u8>) {
-        self.write_xdr(out).unwrap();
+impl<A, R> Procedure<A, R> {
+    pub const fn new(prog: u32, vers: u32, procedure: u32) -> Self {
+        Self {
+            prog,
+            vers,
+            procedure,
+            _types: PhantomData,
+        }
+    }
+
+    pub fn prog(&self) -> u32 {
+        self.prog
+    }
+
+    pub fn vers(&self) -> u32 {
+        self.vers
+    }
+
+    pub fn procedure(&self) -> u32 {
+        self.procedure
     }
 }
 
-/// Trait for XDR deserialization. It contains a blanked impl for all types
-/// implementing [`XDRIn`]
-pub trait Deserialize
-where
-    Self: Sized,
-{
-    fn deserialize(data: &[u8]) -> crate::Result<Self> {
-        let (ret, _) = Self::deserialize_partial(data)?;
-        Ok(ret)
+impl Procedure<Void, Void> {
+    /// The `NULL` procedure (number 0) of a program. Servers implement it without doing
+    /// any work, it is used to check if a server is responding.
+    pub const fn null(prog: u32, vers: u32) -> Self {
+        Self::new(prog, vers, 0)
     }
+}
 
-    fn deserialize_partial(data: &[u8]) -> crate::Result<(Self, usize)>;
+impl<A, R> Clone for Procedure<A, R> {
+    fn clone(&self) -> Self {
+        *self
+    }
 }
 
-impl<T: XDRIn> Deserialize for T {
-    fn deserialize_partial(data: &[u8]) -> crate::Result<(Self, usize)> {
-        let (ret, len) = T::read_xdr(data).map_err(crate::Error::XdrError)?;
-        Ok((ret, len as usize))
+impl<A, R> Copy for Procedure<A, R> {}
+
+impl<A, R> fmt::Debug for Procedure<A, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Procedure")
+            .field("prog", &self.prog)
+            .field("vers", &self.vers)
+            .field("procedure", &self.procedure)
+            .finish()
     }
 }
 
-/// Perform an RPC call with the given client and the given request and
-/// reponse types..
-pub async fn call<C: Client, Req: Serialize, Resp: Deserialize>(
-    client: &mut C,
-    req: &Req,
-    prog: u32,
-    vers: u32,
-    call: u32,
-) -> crate::Result<Resp> {
+/// Client for the procedures of any RPC program. Cloning an `RpcClient` is cheap and
+/// shares the transport and the credentials.
+#[derive(Clone)]
+pub struct RpcClient<C: Client> {
+    client: C,
+    auth: Arc<Auth>,
+}
+
+impl<C: Connect> RpcClient<C> {
+    /// Connect to the server of the given program, looking up its port with the port
+    /// mapper.
+    pub async fn connect<T: Into<IpAddr> + Send>(
+        addr: T,
+        prog: u32,
+        vers: u32,
+    ) -> crate::Result<Self> {
+        Ok(Self::new(C::connect_with_mapper(addr, prog, vers).await?))
+    }
+}
+
+impl<C: Client> RpcClient<C> {
+    /// Perform calls over an already connected `client`, without authentication.
+    pub fn new(client: C) -> Self {
+        Self {
+            client,
+            auth: Default::default(),
+        }
+    }
+
+    /// Authenticate the following calls with `credentials`.
+    pub fn with_credentials(mut self, credentials: Credentials) -> Self {
+        self.auth = Arc::new(Auth::new(credentials));
+        self
+    }
+
+    /// The transport of this client.
+    pub fn client(&self) -> &C {
+        &self.client
+    }
+
+    /// Call `procedure` with `args`.
+    pub async fn call<A: Serialize, R: Deserialize>(
+        &self,
+        procedure: &Procedure<A, R>,
+        args: &A,
+    ) -> crate::Result<R> {
+        call_with_auth(
+            &self.client,
+            &self.auth,
+            args,
+            procedure.prog,
+            procedure.vers,
+            procedure.procedure,
+        )
+        .await
+    }
+
+    /// Call the `NULL` procedure of the given program to check that the server is
+    /// responding and implements the program version.
+    pub async fn ping(&self, prog: u32, vers: u32) -> crate::Result<()> {
+        self.call(&Procedure::null(prog, vers), &Void).await?;
+        Ok(())
+    }
+
+    /// Call `procedure` once for every element of `args` and return the results in the
+    /// same order. The calls are issued without waiting for the preceding replies, such
+    /// that transports supporting concurrent calls pipeline them over the connection.
+    pub async fn batch<A: Serialize, R: Deserialize>(
+        &self,
+        procedure: &Procedure<A, R>,
+        args: &[A],
+    ) -> crate::Result<Vec<R>> {
+        futures::future::try_join_all(args.iter().map(|x| self.call(procedure, x))).await
+    }
+}
+
+/// Broadcast a call of `procedure` over UDP to `addr`, e.g. `255.255.255.255:111` for the
+/// port mapper, and return the results of all servers replying within `timeout`.
+///
+/// Replies which cannot be decoded are ignored. This function blocks the calling thread on
+/// a std `UdpSocket` until `timeout` elapsed, thus it must not be called from a task of
+/// an async executor. Use e.g. `tokio::task::spawn_blocking` there.
+pub fn broadcast_blocking<A: Serialize, R: Deserialize>(
+    addr: SocketAddr,
+    procedure: &Procedure<A, R>,
+    args: &A,
+    timeout: Duration,
+) -> crate::Result<Vec<(SocketAddr, R)>> {
+    let xid = rand::random::<u32>();
     let mut payload = Vec::new();
-    req.serialize(&mut payload);
-    let req = CallBody::new(
-        prog,
-        vers,
-        call,
-        AuthFlavor::AuthNone(None),
-        AuthFlavor::AuthNone(None),
-        payload,
-    );
-    log::debug!("Initiating call from prog={}, call={}", prog, call);
-    let data = client.call(req).await?;
-    log::debug!("Got response with length: {}", data.len());
-    Resp::deserialize(&data)
+    args.serialize(&mut payload);
+    let body =
+        Auth::default().request(procedure.prog, procedure.vers, procedure.procedure, payload);
+    let msg = RpcMessage::new(xid, MessageType::Call(body))
+        .serialise()
+        .map_err(Error::Io)?;
+
+    let bind: SocketAddr = match addr {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => ([0_u16; 8], 0).into(),
+    };
+    let socket = UdpSocket::bind(bind).map_err(Error::Io)?;
+    socket.set_broadcast(true).map_err(Error::Io)?;
+    // there is no record marking on UDP
+    socket.send_to(&msg[4..], addr).map_err(Error::Io)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut ret = Vec::new();
+    let mut buf = vec![0_u8; 4 + u16::MAX as usize];
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        socket
+            .set_read_timeout(Some(deadline - now))
+            .map_err(Error::Io)?;
+        let (len, from) = match socket.recv_from(&mut buf[4..]) {
+            Ok(x) => x,
+            Err(err)
+                if err.kind() == std::io::ErrorKind::WouldBlock
+                    || err.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                break
+            }
+            Err(err) => return Err(Error::Io(err)),
+        };
+        let header = 0x80000000 | len as u32;
+        buf[..4].copy_from_slice(&header.to_be_bytes());
+        let reply = &buf[..len + 4];
+        if message_xid(reply) != Some(xid) {
+            continue;
+        }
+        match decode_reply(reply).and_then(R::deserialize) {
+            Ok(x) => ret.push((from, x)),
+            Err(err) => log::debug!("Ignoring reply from {}: {}", from, err),
+        }
+    }
+    Ok(ret)
+}
+
+/// Reason why the server rejected the authentication of a call (`auth_stat`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthStat {
+    /// Bad credentials (seal broken).
+    BadCredentials,
+    /// The client must begin a new session.
+    RejectedCredentials,
+    /// Bad verifier (seal broken).
+    BadVerifier,
+    /// The verifier expired or was replayed.
+    RejectedVerifier,
+    /// Rejected for security reasons.
+    TooWeak,
+    /// The verifier of the response is invalid.
+    InvalidResponseVerifier,
+    /// Failed for an unknown reason.
+    Failed,
+}
+
+impl From<&AuthError> for AuthStat {
+    fn from(err: &AuthError) -> Self {
+        match err {
+            // a rejected call reporting success is malformed
+            AuthError::Success => AuthStat::Failed,
+            AuthError::BadCredentials => AuthStat::BadCredentials,
+            AuthError::RejectedCredentials => AuthStat::RejectedCredentials,
+            AuthError::BadVerifier => AuthStat::BadVerifier,
+            AuthError::RejectedVerifier => AuthStat::RejectedVerifier,
+            AuthError::TooWeak => AuthStat::TooWeak,
+            AuthError::InvalidResponseVerifier => AuthStat::InvalidResponseVerifier,
+            AuthError::Failed => AuthStat::Failed,
+        }
+    }
+}
+
+/// Decode an RPC reply message and return the result data of the call.
+///
+/// Calls which were not executed successfully by the server are reported with the
+/// corresponding error, e.g. [`crate::Error::RpcProgramUnavailable`] if the server does
+/// not implement the requested program at all.
+pub fn decode_reply(reply: &[u8]) -> crate::Result<&[u8]> {
+    decode_reply_with(reply, &Auth::default())
+}
+
+/// Decode an RPC reply message like [`decode_reply()`] and check its verifier with `auth`.
+pub fn decode_reply_with<'a>(reply: &'a [u8], auth: &Auth) -> crate::Result<&'a [u8]> {
+    let msg = RpcMessage::from_bytes(reply).map_err(crate::Error::Rpc)?;
+    match msg.reply_body() {
+        Some(ReplyBody::Accepted(x)) => match x.status() {
+            AcceptedStatus::Success(data) => {
+                auth.verify(x.auth_verifier())?;
+                Ok(data)
+            }
+            AcceptedStatus::ProgramUnavailable => Err(crate::Error::RpcProgramUnavailable),
+            AcceptedStatus::ProgramMismatch { low, high } => {
+                Err(crate::Error::RpcProgramMismatch {
+                    low: *low,
+                    high: *high,
+                })
+            }
+            AcceptedStatus::ProcedureUnavailable => Err(crate::Error::RpcProcedureUnavailable),
+            AcceptedStatus::GarbageArgs => Err(crate::Error::RpcInvalidArgs),
+            AcceptedStatus::SystemError => Err(crate::Error::RpcSystemError),
+        },
+        Some(ReplyBody::Denied(RejectedReply::RpcVersionMismatch { low, high })) => {
+            Err(crate::Error::RpcVersionMismatch {
+                low: *low,
+                high: *high,
+            })
+        }
+        Some(ReplyBody::Denied(RejectedReply::AuthError(err))) => {
+            Err(crate::Error::RpcAuthError(err.into()))
+        }
+        None => Err(crate::Error::WrongMessageType),
+    }
+}
+
+/// Returns the transaction id of an RPC message, including its record marking header.
+pub fn message_xid(msg: &[u8]) -> Option<u32> {
+    if msg.len() < 8 {
+        return None;
+    }
+    Some(u32::from_be_bytes([msg[4], msg[5], msg[6], msg[7]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use onc_rpc::auth::AuthFlavor;
+    use onc_rpc::{AcceptedReply, MessageType};
+
+    use super::*;
+    use crate::testing::reply;
+    use crate::Error;
+
+    /// A server replying to every call with its arguments.
+    #[derive(Clone)]
+    struct Echo;
+
+    #[async_trait]
+    impl Client for Echo {
+        async fn call(&self, body: Request) -> crate::Result<Bytes> {
+            if body.procedure() == 0 {
+                assert!(body.payload().is_empty());
+            }
+            Ok(reply(1, body.payload()))
+        }
+    }
+
+    const ECHO: Procedure<u32, u32> = Procedure::new(0x20000000, 1, 1);
+
+    fn decode(body: ReplyBody<&[u8], &[u8]>) -> crate::Result<()> {
+        let reply = RpcMessage::new(1, MessageType::Reply(body))
+            .serialise()
+            .unwrap();
+        decode_reply(&reply).map(|_| ())
+    }
+
+    fn accepted(status: AcceptedStatus<&[u8]>) -> crate::Result<()> {
+        let reply = AcceptedReply::new(AuthFlavor::AuthNone(None), status);
+        decode(ReplyBody::Accepted(reply))
+    }
+
+    #[test]
+    fn accepted_status_errors() {
+        let ret = accepted(AcceptedStatus::ProgramUnavailable);
+        assert!(matches!(ret, Err(Error::RpcProgramUnavailable)));
+        let ret = accepted(AcceptedStatus::ProgramMismatch { low: 2, high: 3 });
+        assert!(matches!(
+            ret,
+            Err(Error::RpcProgramMismatch { low: 2, high: 3 })
+        ));
+        let ret = accepted(AcceptedStatus::GarbageArgs);
+        assert!(matches!(ret, Err(Error::RpcInvalidArgs)));
+    }
+
+    #[test]
+    fn rejected_reply_errors() {
+        let ret = decode(ReplyBody::Denied(RejectedReply::RpcVersionMismatch {
+            low: 2,
+            high: 2,
+        }));
+        assert!(matches!(
+            ret,
+            Err(Error::RpcVersionMismatch { low: 2, high: 2 })
+        ));
+        let ret = decode(ReplyBody::Denied(RejectedReply::AuthError(
+            AuthError::TooWeak,
+        )));
+        assert!(matches!(ret, Err(Error::RpcAuthError(AuthStat::TooWeak))));
+    }
+
+    #[tokio::test]
+    async fn procedure_calls() {
+        let rpc = RpcClient::new(Echo);
+
+        rpc.ping(ECHO.prog(), ECHO.vers()).await.unwrap();
+        assert_eq!(rpc.call(&ECHO, &7).await.unwrap(), 7);
+        assert_eq!(rpc.batch(&ECHO, &[1, 2, 3]).await.unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn broadcast_replies() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0_u8; 1024];
+            let (len, from) = server.recv_from(&mut buf[4..]).unwrap();
+            buf[..4].copy_from_slice(&(0x80000000 | len as u32).to_be_bytes());
+            let msg = RpcMessage::from_bytes(&buf[..len + 4]).unwrap();
+            let call = msg.call_body().unwrap();
+            assert_eq!(call.procedure(), 1);
+            // echo the arguments without record marking
+            let reply = reply(msg.xid(), call.payload());
+            server.send_to(&reply[4..], from).unwrap();
+        });
+
+        let ret = broadcast_blocking(addr, &ECHO, &42, Duration::from_millis(200)).unwrap();
+
+        assert_eq!(ret, [(addr, 42)]);
+    }
 }
@@ -0,0 +1,132 @@
+use std::convert::TryFrom;
+
+use super::{esr, parse_nr1, ScpiError};
+use crate::core::client::CoreClient;
+use crate::rpc::Client;
+use crate::Error;
+
+/// Bits of the standard event status register reporting an error.
+const ESR_ERRORS: u8 =
+    esr::QUERY_ERROR | esr::DEVICE_ERROR | esr::EXECUTION_ERROR | esr::COMMAND_ERROR;
+
+/// Upper bound of the entries read from the error queue, in case the device never
+/// reports `0,"No error"`.
+const MAX_ERRORS: usize = 256;
+
+impl<T: Client> CoreClient<T> {
+    /// Write a SCPI command, terminated by a newline.
+    pub async fn scpi_write(&mut self, command: &str) -> crate::Result<()> {
+        let mut data = command.as_bytes().to_vec();
+        data.push(b'\n');
+        self.device_write(data).await.map(|_| ())
+    }
+
+    /// Write a SCPI query and return the response without the trailing newline.
+    pub async fn scpi_query(&mut self, query: &str) -> crate::Result<String> {
+        self.scpi_write(query).await?;
+        let data = self.device_read().await?;
+        let response = String::from_utf8(data).map_err(|err| {
+            Error::InvalidScpiResponse(String::from_utf8_lossy(err.as_bytes()).into())
+        })?;
+        Ok(response.trim_end_matches(['\n', '\r']).to_string())
+    }
+
+    /// Drain the error queue with `SYSTem:ERRor?` until the device reports `0,"No error"`.
+    pub async fn scpi_errors(&mut self) -> crate::Result<Vec<ScpiError>> {
+        let mut errors = Vec::new();
+        while errors.len() < MAX_ERRORS {
+            let error = ScpiError::parse(&self.scpi_query("SYSTem:ERRor?").await?)?;
+            if error.code == 0 {
+                break;
+            }
+            errors.push(error);
+        }
+        Ok(errors)
+    }
+
+    /// Write a SCPI command, then check the standard event status register (`*ESR?`) and
+    /// the error queue. Fails with [`Error::ScpiCommandFailed`] if an error bit is set or
+    /// the queue is not empty. Both are cleared in any case.
+    pub async fn scpi_write_checked(&mut self, command: &str) -> crate::Result<()> {
+        self.scpi_write(command).await?;
+        let response = self.scpi_query("*ESR?").await?;
+        let esr = u8::try_from(parse_nr1(&response)?)
+            .map_err(|_| Error::InvalidScpiResponse(response))?;
+        let errors = self.scpi_errors().await?;
+        if esr & ESR_ERRORS != 0 || !errors.is_empty() {
+            return Err(Error::ScpiCommandFailed { esr, errors });
+        }
+        Ok(())
+    }
+
+    /// Wait until all pending operations of the device completed with `*OPC?`.
+    ///
+    /// The wait is bounded by the I/O timeout of the link.
+    pub async fn scpi_opc(&mut self) -> crate::Result<()> {
+        self.scpi_write_and_wait("").await
+    }
+
+    /// Write a SCPI command followed by `*OPC?` and wait until the device completed it.
+    pub async fn scpi_write_and_wait(&mut self, command: &str) -> crate::Result<()> {
+        let query = if command.is_empty() {
+            "*OPC?".to_string()
+        } else {
+            format!("{};*OPC?", command)
+        };
+        let response = self.scpi_query(&query).await?;
+        match parse_nr1(&response) {
+            Ok(1) => Ok(()),
+            _ => Err(Error::InvalidScpiResponse(response)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scpi::{parse_list, parse_nr3, Router};
+    use crate::sim::SimClient;
+    use crate::Connection;
+
+    fn meter() -> Router<Vec<f64>> {
+        Router::new("ACME,Meter,1,0.1", vec![0.0; 3])
+            .command("CONFigure:RANGe", |ranges: &mut Vec<f64>, args| {
+                let value = args.f64(0)?;
+                if value <= 0.0 {
+                    return Err(ScpiError::DATA_OUT_OF_RANGE);
+                }
+                ranges.iter_mut().for_each(|x| *x = value);
+                Ok(())
+            })
+            .query("FETCh", |ranges: &mut Vec<f64>, _| {
+                let values: Vec<_> = ranges.iter().map(|x| format!("{:E}", x / 2.0)).collect();
+                Ok(values.join(","))
+            })
+    }
+
+    #[tokio::test]
+    async fn checked_commands() {
+        let client = SimClient::new(meter());
+        let conn = Connection::from_client(client);
+        let mut link = conn.create_link("inst0").await.unwrap();
+
+        link.scpi_write_checked("CONF:RANG 10").await.unwrap();
+        link.scpi_write_and_wait("CONF:RANG 20").await.unwrap();
+        link.scpi_opc().await.unwrap();
+        let values = link.scpi_query("FETC?").await.unwrap();
+        assert_eq!(parse_list(&values, parse_nr3).unwrap(), [10.0; 3]);
+
+        let ret = link.scpi_write_checked("CONF:RANG -1;FOO").await;
+        match ret {
+            Err(Error::ScpiCommandFailed { esr, errors }) => {
+                assert_eq!(esr, esr::EXECUTION_ERROR | esr::COMMAND_ERROR);
+                assert_eq!(
+                    errors,
+                    [ScpiError::DATA_OUT_OF_RANGE, ScpiError::UNDEFINED_HEADER]
+                );
+            }
+            _ => panic!("unexpected result: {:?}", ret),
+        }
+        assert!(link.scpi_errors().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,253 @@
+//! SCPI (Standard Commands for Programmable Instruments).
+//!
+//! On the client side, [`CoreClient`][crate::CoreClient] offers SCPI helpers such as
+//! [`CoreClient::scpi_write_checked()`][crate::CoreClient::scpi_write_checked()] and
+//! [`CoreClient::scpi_errors()`][crate::CoreClient::scpi_errors()]. The functions of this
+//! module parse the responses.
+//!
+//! [`Router`] dispatches the SCPI commands received by a simulated instrument to
+//! handlers, see [`crate::sim`].
+
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::Error;
+
+mod client;
+mod router;
+
+pub use router::{Args, Router};
+
+/// Bits of the standard event status register, as returned by `*ESR?`.
+pub mod esr {
+    pub const OPERATION_COMPLETE: u8 = 0x01;
+    pub const QUERY_ERROR: u8 = 0x04;
+    pub const DEVICE_ERROR: u8 = 0x08;
+    pub const EXECUTION_ERROR: u8 = 0x10;
+    pub const COMMAND_ERROR: u8 = 0x20;
+}
+
+/// Bits of the status byte, as returned by `*STB?` or `device_readstb`.
+pub mod stb {
+    /// The error queue is not empty.
+    pub const ERROR_AVAILABLE: u8 = 0x04;
+    /// A response is available.
+    pub const MESSAGE_AVAILABLE: u8 = 0x10;
+    /// An enabled bit of the standard event status register is set.
+    pub const EVENT_STATUS: u8 = 0x20;
+}
+
+/// An entry of the SCPI error queue. Negative codes are defined by the SCPI standard,
+/// positive codes are device specific.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScpiError {
+    pub code: i32,
+    pub message: Cow<'static, str>,
+}
+
+impl ScpiError {
+    pub const NO_ERROR: ScpiError = ScpiError::new(0, "No error");
+    pub const COMMAND_ERROR: ScpiError = ScpiError::new(-100, "Command error");
+    pub const DATA_TYPE_ERROR: ScpiError = ScpiError::new(-104, "Data type error");
+    pub const PARAMETER_NOT_ALLOWED: ScpiError = ScpiError::new(-108, "Parameter not allowed");
+    pub const MISSING_PARAMETER: ScpiError = ScpiError::new(-109, "Missing parameter");
+    pub const UNDEFINED_HEADER: ScpiError = ScpiError::new(-113, "Undefined header");
+    pub const EXECUTION_ERROR: ScpiError = ScpiError::new(-200, "Execution error");
+    pub const DATA_OUT_OF_RANGE: ScpiError = ScpiError::new(-222, "Data out of range");
+    pub const QUEUE_OVERFLOW: ScpiError = ScpiError::new(-350, "Queue overflow");
+    pub const QUERY_INTERRUPTED: ScpiError = ScpiError::new(-410, "Query INTERRUPTED");
+    pub const QUERY_UNTERMINATED: ScpiError = ScpiError::new(-420, "Query UNTERMINATED");
+
+    pub const fn new(code: i32, message: &'static str) -> Self {
+        Self {
+            code,
+            message: Cow::Borrowed(message),
+        }
+    }
+
+    /// Parse an error as returned by `SYSTem:ERRor?`, e.g. `-113,"Undefined header"`.
+    pub fn parse(response: &str) -> crate::Result<Self> {
+        let response = response.trim();
+        let invalid = || Error::InvalidScpiResponse(response.to_string());
+        let (code, message) = response.split_once(',').ok_or_else(invalid)?;
+        Ok(Self {
+            code: code.trim().parse().map_err(|_| invalid())?,
+            message: Cow::Owned(unquote(message.trim())),
+        })
+    }
+
+    /// The bit of the standard event status register set by this error.
+    pub fn esr_bit(&self) -> u8 {
+        match self.code {
+            0 => 0,
+            -199..=-100 => esr::COMMAND_ERROR,
+            -299..=-200 => esr::EXECUTION_ERROR,
+            -499..=-400 => esr::QUERY_ERROR,
+            _ => esr::DEVICE_ERROR,
+        }
+    }
+}
+
+/// Formats the error as returned by `SYSTem:ERRor?`, e.g. `-113,"Undefined header"`.
+impl fmt::Display for ScpiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},\"{}\"", self.code, self.message.replace('"', "\"\""))
+    }
+}
+
+/// Split `text` at `separator`, ignoring separators within quoted strings.
+fn split_unquoted(text: &str, separator: char) -> Vec<&str> {
+    let mut ret = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == separator => {
+                ret.push(&text[start..i]);
+                start = i + 1;
+            }
+            None => {}
+        }
+    }
+    ret.push(&text[start..]);
+    ret
+}
+
+/// Remove the quotes of a string, unescaping doubled quotes.
+fn unquote(text: &str) -> String {
+    for quote in ['"', '\''] {
+        if text.len() >= 2 && text.starts_with(quote) && text.ends_with(quote) {
+            let inner = &text[1..text.len() - 1];
+            return inner.replace(&format!("{}{}", quote, quote), &quote.to_string());
+        }
+    }
+    text.to_string()
+}
+
+fn invalid(response: &str) -> Error {
+    Error::InvalidScpiResponse(response.to_string())
+}
+
+/// Parse an integer in NR1 format, e.g. `+42`.
+pub fn parse_nr1(response: &str) -> crate::Result<i64> {
+    let response = response.trim();
+    response
+        .strip_prefix('+')
+        .unwrap_or(response)
+        .parse()
+        .map_err(|_| invalid(response))
+}
+
+/// Parse a number in NR2 format, e.g. `-1.25`. Integers in NR1 format are accepted as well.
+pub fn parse_nr2(response: &str) -> crate::Result<f64> {
+    let response = response.trim();
+    if response.contains(['e', 'E']) {
+        return Err(invalid(response));
+    }
+    parse_nr3(response)
+}
+
+/// Parse a number in NR3 format, e.g. `+1.250000E-03`. NR1 and NR2 numbers are accepted
+/// as well. The values SCPI uses to represent not-a-number (`9.91E37`) and infinity
+/// (`9.9E37`, `-9.9E37`) are converted accordingly.
+pub fn parse_nr3(response: &str) -> crate::Result<f64> {
+    let response = response.trim();
+    let value: f64 = response
+        .strip_prefix('+')
+        .unwrap_or(response)
+        .parse()
+        .map_err(|_| invalid(response))?;
+    if !value.is_finite() {
+        // `inf` and `nan` are not SCPI numbers
+        return Err(invalid(response));
+    }
+    Ok(if value == 9.91e37 {
+        f64::NAN
+    } else if value.abs() == 9.9e37 {
+        value.signum() * f64::INFINITY
+    } else {
+        value
+    })
+}
+
+/// Parse a boolean, `1`, `0`, `ON` or `OFF`.
+pub fn parse_bool(response: &str) -> crate::Result<bool> {
+    let response = response.trim();
+    match response.to_ascii_uppercase().as_str() {
+        "1" | "ON" => Ok(true),
+        "0" | "OFF" => Ok(false),
+        _ => Err(invalid(response)),
+    }
+}
+
+/// Parse a string response, removing its quotes.
+pub fn parse_string(response: &str) -> String {
+    unquote(response.trim())
+}
+
+/// Parse a comma-separated list with `parse` applied to every element, e.g.
+/// `parse_list("1,2,3", parse_nr1)`. Commas within quoted strings do not separate
+/// elements. An empty response is an empty list.
+pub fn parse_list<T, F>(response: &str, parse: F) -> crate::Result<Vec<T>>
+where
+    F: Fn(&str) -> crate::Result<T>,
+{
+    let response = response.trim();
+    if response.is_empty() {
+        return Ok(Vec::new());
+    }
+    split_unquoted(response, ',')
+        .into_iter()
+        .map(parse)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_responses() {
+        let err = ScpiError::parse("-113,\"Undefined header\"\n").unwrap();
+        assert_eq!(err, ScpiError::UNDEFINED_HEADER);
+        let err = ScpiError::parse("+0,\"No error\"").unwrap();
+        assert_eq!(err, ScpiError::NO_ERROR);
+        let err = ScpiError::parse("-222,\"Data out of range;\"\"VOLT\"\" > 30\"").unwrap();
+        assert_eq!(err.message, "Data out of range;\"VOLT\" > 30");
+        assert_eq!(ScpiError::parse(&err.to_string()).unwrap(), err);
+        assert!(matches!(
+            ScpiError::parse("No error"),
+            Err(Error::InvalidScpiResponse(_))
+        ));
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_nr1("+42\n").unwrap(), 42);
+        assert_eq!(parse_nr1("-7").unwrap(), -7);
+        assert!(parse_nr1("1.5").is_err());
+        assert_eq!(parse_nr2("-1.25").unwrap(), -1.25);
+        assert_eq!(parse_nr2("3").unwrap(), 3.0);
+        assert!(parse_nr2("1E3").is_err());
+        assert_eq!(parse_nr3("+1.250000E-03").unwrap(), 1.25e-3);
+        assert!(parse_nr3("9.91E37").unwrap().is_nan());
+        assert_eq!(parse_nr3("-9.9E+37").unwrap(), f64::NEG_INFINITY);
+        assert!(parse_nr3("inf").is_err());
+        assert!(parse_nr3("").is_err());
+    }
+
+    #[test]
+    fn booleans_and_lists() {
+        assert!(parse_bool("ON").unwrap());
+        assert!(!parse_bool("0\n").unwrap());
+        assert!(parse_bool("2").is_err());
+        assert_eq!(parse_list("1,2,3", parse_nr1).unwrap(), [1, 2, 3]);
+        assert_eq!(parse_list("", parse_nr1).unwrap(), Vec::<i64>::new());
+        assert!(parse_list("1,,3", parse_nr1).is_err());
+        let names = parse_list("\"a,b\",'c'", |x| Ok(parse_string(x))).unwrap();
+        assert_eq!(names, ["a,b", "c"]);
+    }
+}
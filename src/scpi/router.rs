@@ -0,0 +1,595 @@
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+
+use super::{esr, split_unquoted, stb, unquote, ScpiError};
+use crate::sim::Device;
+use crate::VxiErrorCode;
+
+/// Maximum number of entries of the error queue.
+const ERROR_QUEUE_SIZE: usize = 16;
+
+type CommandHandler<S> = Box<dyn FnMut(&mut S, &Args) -> Result<(), ScpiError> + Send>;
+type QueryHandler<S> = Box<dyn FnMut(&mut S, &Args) -> Result<String, ScpiError> + Send>;
+type ResetHandler<S> = Box<dyn FnMut(&mut S) + Send>;
+
+enum Handler<S> {
+    Command(CommandHandler<S>),
+    Query(QueryHandler<S>),
+}
+
+struct Route<S> {
+    pattern: Vec<Node>,
+    handler: Handler<S>,
+}
+
+/// A node of a command pattern such as `[SOURce#]`.
+#[derive(Debug)]
+struct Node {
+    short: String,
+    long: String,
+    optional: bool,
+    suffix: bool,
+}
+
+impl Node {
+    fn parse(text: &str) -> Self {
+        let (text, optional) = match text.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+            Some(x) => (x, true),
+            None => (text, false),
+        };
+        let (text, suffix) = match text.strip_suffix('#') {
+            Some(x) => (x, true),
+            None => (text, false),
+        };
+        Self {
+            short: text
+                .chars()
+                .take_while(|c| !c.is_ascii_lowercase())
+                .collect(),
+            long: text.to_ascii_uppercase(),
+            optional,
+            suffix,
+        }
+    }
+
+    /// Match a mnemonic of a header. Returns the numeric suffix, 1 if omitted.
+    fn matches(&self, word: &str) -> Option<u32> {
+        let (name, suffix) = if self.suffix {
+            let name = word.trim_end_matches(|c: char| c.is_ascii_digit());
+            match &word[name.len()..] {
+                "" => (name, 1),
+                digits => (name, digits.parse().ok()?),
+            }
+        } else {
+            (word, 1)
+        };
+        let name = name.to_ascii_uppercase();
+        (name == self.short || name == self.long).then_some(suffix)
+    }
+}
+
+/// Parse a pattern such as `SYSTem:ERRor[:NEXT]` into its nodes.
+fn parse_pattern(pattern: &str) -> Vec<Node> {
+    pattern
+        .trim_start_matches(':')
+        .replace("[:", ":[")
+        .split(':')
+        .map(Node::parse)
+        .collect()
+}
+
+/// Match the mnemonics of a header against a pattern, collecting the numeric suffixes.
+fn match_pattern(pattern: &[Node], words: &[&str], suffixes: &mut Vec<u32>) -> bool {
+    let Some((node, rest)) = pattern.split_first() else {
+        return words.is_empty();
+    };
+    if let Some((word, others)) = words.split_first() {
+        if let Some(suffix) = node.matches(word) {
+            let len = suffixes.len();
+            if node.suffix {
+                suffixes.push(suffix);
+            }
+            if match_pattern(rest, others, suffixes) {
+                return true;
+            }
+            suffixes.truncate(len);
+        }
+    }
+    if node.optional {
+        let len = suffixes.len();
+        if node.suffix {
+            suffixes.push(1);
+        }
+        if match_pattern(rest, words, suffixes) {
+            return true;
+        }
+        suffixes.truncate(len);
+    }
+    false
+}
+
+/// The numeric suffixes of the header and the parameters of a command.
+#[derive(Debug, Default)]
+pub struct Args {
+    suffixes: Vec<u32>,
+    params: Vec<String>,
+}
+
+impl Args {
+    fn parse(params: &str) -> Self {
+        let params = if params.trim().is_empty() {
+            Vec::new()
+        } else {
+            split_unquoted(params, ',')
+                .into_iter()
+                .map(|x| x.trim().to_string())
+                .collect()
+        };
+        Self {
+            suffixes: Vec::new(),
+            params,
+        }
+    }
+
+    /// The numeric suffix of the `index`-th node of the pattern with a `#`, 1 if omitted.
+    pub fn suffix(&self, index: usize) -> u32 {
+        self.suffixes.get(index).copied().unwrap_or(1)
+    }
+
+    /// The number of parameters.
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    /// A parameter, with the quotes of string parameters removed.
+    pub fn str(&self, index: usize) -> Result<String, ScpiError> {
+        self.raw(index).map(unquote)
+    }
+
+    pub fn f64(&self, index: usize) -> Result<f64, ScpiError> {
+        self.raw(index)?
+            .parse()
+            .map_err(|_| ScpiError::DATA_TYPE_ERROR)
+    }
+
+    pub fn i64(&self, index: usize) -> Result<i64, ScpiError> {
+        self.raw(index)?
+            .parse()
+            .map_err(|_| ScpiError::DATA_TYPE_ERROR)
+    }
+
+    /// A boolean parameter, `ON`, `OFF`, `1` or `0`.
+    pub fn bool(&self, index: usize) -> Result<bool, ScpiError> {
+        match self.raw(index)?.to_ascii_uppercase().as_str() {
+            "ON" | "1" => Ok(true),
+            "OFF" | "0" => Ok(false),
+            _ => Err(ScpiError::DATA_TYPE_ERROR),
+        }
+    }
+
+    fn raw(&self, index: usize) -> Result<&str, ScpiError> {
+        match self.params.get(index) {
+            Some(x) if !x.is_empty() => Ok(x),
+            _ => Err(ScpiError::MISSING_PARAMETER),
+        }
+    }
+}
+
+/// A SCPI instrument dispatching commands to handlers operating on a state `S`.
+///
+/// Command patterns are written in the usual SCPI notation, e.g. `[SOURce#]:FREQuency`:
+/// headers match the short form (the upper case part) or the long form of every node,
+/// case-insensitive. Nodes in brackets are optional and `#` accepts a numeric suffix,
+/// see [`Args::suffix()`].
+///
+/// The IEEE 488.2 common commands, the status registers and the error queue
+/// (`SYSTem:ERRor[:NEXT]?`) are handled by the router. Failed commands push their error
+/// to the queue.
+pub struct Router<S> {
+    state: S,
+    idn: String,
+    routes: Vec<Route<S>>,
+    reset: Option<ResetHandler<S>>,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    errors: VecDeque<ScpiError>,
+    esr: u8,
+    ese: u8,
+}
+
+impl<S> Router<S> {
+    /// A router answering `*IDN?` with `idn`.
+    pub fn new<T: Into<String>>(idn: T, state: S) -> Self {
+        Self {
+            state,
+            idn: idn.into(),
+            routes: Vec::new(),
+            reset: None,
+            input: Vec::new(),
+            output: Vec::new(),
+            errors: VecDeque::new(),
+            esr: 0,
+            ese: 0,
+        }
+    }
+
+    /// Handle the command matching `pattern`.
+    pub fn command<F>(mut self, pattern: &str, handler: F) -> Self
+    where
+        F: FnMut(&mut S, &Args) -> Result<(), ScpiError> + Send + 'static,
+    {
+        self.routes.push(Route {
+            pattern: parse_pattern(pattern),
+            handler: Handler::Command(Box::new(handler)),
+        });
+        self
+    }
+
+    /// Handle the query matching `pattern`, without the trailing `?`.
+    pub fn query<F>(mut self, pattern: &str, handler: F) -> Self
+    where
+        F: FnMut(&mut S, &Args) -> Result<String, ScpiError> + Send + 'static,
+    {
+        self.routes.push(Route {
+            pattern: parse_pattern(pattern),
+            handler: Handler::Query(Box::new(handler)),
+        });
+        self
+    }
+
+    /// Reset the state on `*RST`.
+    pub fn on_reset<F>(mut self, handler: F) -> Self
+    where
+        F: FnMut(&mut S) + Send + 'static,
+    {
+        self.reset = Some(Box::new(handler));
+        self
+    }
+
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut S {
+        &mut self.state
+    }
+
+    /// Push an error to the error queue and set the corresponding bit of the standard
+    /// event status register.
+    pub fn push_error(&mut self, error: ScpiError) {
+        self.esr |= error.esr_bit();
+        if self.errors.len() < ERROR_QUEUE_SIZE {
+            self.errors.push_back(error);
+        } else if let Some(last) = self.errors.back_mut() {
+            *last = ScpiError::QUEUE_OVERFLOW;
+        }
+    }
+
+    /// Execute a program message, e.g. `VOLT 5;CURR?`, and return the responses of its
+    /// queries joined with `;`.
+    pub fn execute(&mut self, message: &str) -> String {
+        let mut responses = Vec::new();
+        let mut path: Vec<&str> = Vec::new();
+        for unit in split_unquoted(message, ';') {
+            let unit = unit.trim();
+            if unit.is_empty() {
+                continue;
+            }
+            let (header, params) = match unit.find(char::is_whitespace) {
+                Some(i) => (&unit[..i], &unit[i..]),
+                None => (unit, ""),
+            };
+            let args = Args::parse(params);
+            let (header, query) = match header.strip_suffix('?') {
+                Some(x) => (x, true),
+                None => (header, false),
+            };
+            let ret = if let Some(common) = header.strip_prefix('*') {
+                self.common(common, query, &args)
+            } else {
+                let mut words = if header.starts_with(':') {
+                    Vec::new()
+                } else {
+                    path.clone()
+                };
+                words.extend(header.trim_start_matches(':').split(':'));
+                path = words[..words.len() - 1].to_vec();
+                self.dispatch(&words, query, args)
+            };
+            match ret {
+                Ok(Some(response)) => responses.push(response),
+                Ok(None) => {}
+                Err(err) => self.push_error(err),
+            }
+        }
+        responses.join(";")
+    }
+
+    fn common(
+        &mut self,
+        name: &str,
+        query: bool,
+        args: &Args,
+    ) -> Result<Option<String>, ScpiError> {
+        let takes_param = !query && name.eq_ignore_ascii_case("ESE");
+        if !takes_param && !args.is_empty() {
+            return Err(ScpiError::PARAMETER_NOT_ALLOWED);
+        }
+        let ret = match (name.to_ascii_uppercase().as_str(), query) {
+            ("IDN", true) => self.idn.clone(),
+            ("RST", false) => {
+                if let Some(reset) = self.reset.as_mut() {
+                    reset(&mut self.state);
+                }
+                return Ok(None);
+            }
+            ("CLS", false) => {
+                self.errors.clear();
+                self.esr = 0;
+                return Ok(None);
+            }
+            ("ESR", true) => std::mem::take(&mut self.esr).to_string(),
+            ("ESE", false) => {
+                let value = args.i64(0)?;
+                self.ese = u8::try_from(value).map_err(|_| ScpiError::DATA_OUT_OF_RANGE)?;
+                return Ok(None);
+            }
+            ("ESE", true) => self.ese.to_string(),
+            ("OPC", false) => {
+                self.esr |= esr::OPERATION_COMPLETE;
+                return Ok(None);
+            }
+            ("OPC", true) => "1".to_string(),
+            ("STB", true) => self.status().to_string(),
+            ("WAI", false) => return Ok(None),
+            _ => return Err(ScpiError::UNDEFINED_HEADER),
+        };
+        Ok(Some(ret))
+    }
+
+    fn dispatch(
+        &mut self,
+        words: &[&str],
+        query: bool,
+        mut args: Args,
+    ) -> Result<Option<String>, ScpiError> {
+        if words.iter().any(|x| x.is_empty()) {
+            return Err(ScpiError::UNDEFINED_HEADER);
+        }
+        if query
+            && match_pattern(
+                &parse_pattern("SYSTem:ERRor[:NEXT]"),
+                words,
+                &mut args.suffixes,
+            )
+        {
+            let error = self.errors.pop_front().unwrap_or(ScpiError::NO_ERROR);
+            return Ok(Some(error.to_string()));
+        }
+        for route in &mut self.routes {
+            args.suffixes.clear();
+            if !match_pattern(&route.pattern, words, &mut args.suffixes) {
+                continue;
+            }
+            match &mut route.handler {
+                Handler::Command(handler) if !query => {
+                    return handler(&mut self.state, &args).map(|_| None)
+                }
+                Handler::Query(handler) if query => {
+                    return handler(&mut self.state, &args).map(Some)
+                }
+                _ => {}
+            }
+        }
+        Err(ScpiError::UNDEFINED_HEADER)
+    }
+
+    fn status(&self) -> u8 {
+        let mut ret = 0;
+        if !self.errors.is_empty() {
+            ret |= stb::ERROR_AVAILABLE;
+        }
+        if !self.output.is_empty() {
+            ret |= stb::MESSAGE_AVAILABLE;
+        }
+        if self.esr & self.ese != 0 {
+            ret |= stb::EVENT_STATUS;
+        }
+        ret
+    }
+}
+
+/// Program messages are terminated by `end` or a newline. Responses are terminated by a
+/// newline. Sending a new message before the response is read discards the response with
+/// a `Query INTERRUPTED` error, reading without a pending response fails with
+/// `Query UNTERMINATED` and [`VxiErrorCode::IoTimeout`].
+impl<S: Send> Device for Router<S> {
+    fn write(&mut self, data: &[u8], end: bool) -> Result<(), VxiErrorCode> {
+        if self.input.is_empty() && !self.output.is_empty() {
+            self.output.clear();
+            self.push_error(ScpiError::QUERY_INTERRUPTED);
+        }
+        self.input.extend_from_slice(data);
+        if !end && !self.input.ends_with(b"\n") {
+            return Ok(());
+        }
+        let input = std::mem::take(&mut self.input);
+        for message in String::from_utf8_lossy(&input).split('\n') {
+            let response = self.execute(message);
+            if !response.is_empty() {
+                self.output.extend_from_slice(response.as_bytes());
+                self.output.push(b'\n');
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, max: usize) -> Result<(Vec<u8>, bool), VxiErrorCode> {
+        if self.output.is_empty() {
+            self.push_error(ScpiError::QUERY_UNTERMINATED);
+            return Err(VxiErrorCode::IoTimeout);
+        }
+        let data: Vec<u8> = self.output.drain(..max.min(self.output.len())).collect();
+        Ok((data, self.output.is_empty()))
+    }
+
+    fn status_byte(&mut self) -> u8 {
+        self.status()
+    }
+
+    fn clear(&mut self) {
+        self.input.clear();
+        self.output.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimClient;
+    use crate::Connection;
+
+    #[derive(Default)]
+    struct Supply {
+        voltage: [f64; 2],
+        output: bool,
+    }
+
+    fn supply() -> Router<Supply> {
+        Router::new("ACME,Supply,1,0.1", Supply::default())
+            .command("[SOURce#]:VOLTage[:LEVel]", |s: &mut Supply, args| {
+                let channel = args.suffix(0) as usize;
+                let value = args.f64(0)?;
+                if !(0.0..=30.0).contains(&value) {
+                    return Err(ScpiError::DATA_OUT_OF_RANGE);
+                }
+                *s.voltage
+                    .get_mut(channel - 1)
+                    .ok_or(ScpiError::UNDEFINED_HEADER)? = value;
+                Ok(())
+            })
+            .query("[SOURce#]:VOLTage[:LEVel]", |s: &mut Supply, args| {
+                let channel = args.suffix(0) as usize;
+                let value = s
+                    .voltage
+                    .get(channel - 1)
+                    .ok_or(ScpiError::UNDEFINED_HEADER)?;
+                Ok(value.to_string())
+            })
+            .command("OUTPut[:STATe]", |s: &mut Supply, args| {
+                s.output = args.bool(0)?;
+                Ok(())
+            })
+            .query("OUTPut[:STATe]", |s: &mut Supply, _| {
+                Ok(if s.output { "1" } else { "0" }.to_string())
+            })
+            .query("SYSTem:ECHO", |_: &mut Supply, args| args.str(0))
+            .on_reset(|s: &mut Supply| *s = Supply::default())
+    }
+
+    #[test]
+    fn pattern_matching() {
+        let mut router = supply();
+        assert_eq!(router.execute("volt 1.5"), "");
+        assert_eq!(router.execute("SOUR2:VOLTAGE:LEV 2.5"), "");
+        assert_eq!(router.execute("source1:volt?"), "1.5");
+        assert_eq!(router.execute(":SOUR2:VOLT?"), "2.5");
+        assert_eq!(router.execute("OUTP ON;OUTP:STAT?"), "1");
+        assert_eq!(router.execute("SYST:ECHO? \"a,\"\"b\"\"\""), "a,\"b\"");
+        assert_eq!(router.execute("SYST:ERR?"), "0,\"No error\"");
+
+        assert_eq!(router.execute("VOLTS 1"), "");
+        assert_eq!(router.execute("SOUR3:VOLT 1"), "");
+        assert_eq!(router.execute("SOURx:VOLT 1"), "");
+        assert_eq!(router.execute("OUTP:STAT"), "");
+        assert_eq!(router.execute("OUTP maybe"), "");
+        assert_eq!(router.execute("VOLT 100"), "");
+        let errors: Vec<_> = (0..7).map(|_| router.execute("SYST:ERR:NEXT?")).collect();
+        assert_eq!(
+            errors,
+            [
+                "-113,\"Undefined header\"",
+                "-113,\"Undefined header\"",
+                "-113,\"Undefined header\"",
+                "-109,\"Missing parameter\"",
+                "-104,\"Data type error\"",
+                "-222,\"Data out of range\"",
+                "0,\"No error\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn compound_commands() {
+        let mut router = supply();
+        // relative headers continue at the node of the previous header
+        assert_eq!(router.execute("SOUR2:VOLT 3;VOLT?;:OUTP 1;OUTP?"), "3;1");
+        assert_eq!(router.execute("SOUR2:VOLT?;SOUR2:VOLT?"), "3");
+        assert_eq!(router.execute("SYST:ERR?"), "-113,\"Undefined header\"");
+    }
+
+    #[test]
+    fn common_commands() {
+        let mut router = supply();
+        assert_eq!(router.execute("*IDN?"), "ACME,Supply,1,0.1");
+        router.execute("VOLT 5;*RST");
+        assert_eq!(router.execute("VOLT?"), "0");
+
+        router.execute("*ESE 60;FOO;VOLT 100;*OPC");
+        assert_eq!(router.status(), stb::ERROR_AVAILABLE | stb::EVENT_STATUS);
+        assert_eq!(router.execute("*ESE?;*ESR?;*ESR?"), "60;49;0");
+        router.execute("*CLS");
+        assert_eq!(router.execute("*STB?;*OPC?"), "0;1");
+        router.execute("*FOO;*ESE 256");
+        assert_eq!(
+            router.execute("SYST:ERR?;:SYST:ERR?"),
+            "-113,\"Undefined header\";-222,\"Data out of range\""
+        );
+    }
+
+    #[test]
+    fn error_queue_overflow() {
+        let mut router = supply();
+        for _ in 0..20 {
+            router.execute("FOO");
+        }
+        let errors: Vec<_> = (0..17).map(|_| router.execute("SYST:ERR?")).collect();
+        assert_eq!(errors[14], "-113,\"Undefined header\"");
+        assert_eq!(errors[15], "-350,\"Queue overflow\"");
+        assert_eq!(errors[16], "0,\"No error\"");
+    }
+
+    #[tokio::test]
+    async fn simulated_instrument() {
+        let client = SimClient::new(supply());
+        let conn = Connection::from_client(client.clone());
+        let mut link = conn.create_link("inst0").await.unwrap();
+
+        link.device_write(b"SOUR2:VOLT 12.5\n".to_vec())
+            .await
+            .unwrap();
+        link.device_write(b"SOUR2:VOLT?\n".to_vec()).await.unwrap();
+        assert_eq!(link.device_read().await.unwrap(), b"12.5\n");
+        assert_eq!(link.device_readstb().await.unwrap(), 0);
+
+        // a new message discards the pending response
+        link.device_write(b"*IDN?\n".to_vec()).await.unwrap();
+        link.device_write(b"*OPC?\n".to_vec()).await.unwrap();
+        assert_eq!(link.device_read().await.unwrap(), b"1\n");
+        assert_eq!(link.device_readstb().await.unwrap(), stb::ERROR_AVAILABLE);
+        link.device_write(b"SYST:ERR?\n".to_vec()).await.unwrap();
+        assert_eq!(
+            link.device_read().await.unwrap(),
+            b"-410,\"Query INTERRUPTED\"\n"
+        );
+
+        assert!(link.device_read().await.is_err());
+        link.device_clear().await.unwrap();
+        link.destroy_link().await.unwrap();
+        assert_eq!(client.with_device(|x| x.state().voltage), [0.0, 12.5]);
+    }
+}
@@ -0,0 +1,293 @@
+//! In-process simulation of instruments.
+//!
+//! A simulated instrument implements [`Device`]. [`SimClient`] is a transport serving the
+//! calls of the VXI-11 core channel with the device, without any network connection:
+//!
+//! ```ignore
+//! let client = SimClient::new(device);
+//! let mut link = Connection::from_client(client).create_link("inst0").await?;
+//! ```
+//!
+//! [`crate::scpi::Router`] implements a SCPI instrument.
+
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use onc_rpc::auth::AuthFlavor;
+use onc_rpc::{AcceptedReply, AcceptedStatus, MessageType, ReplyBody, RpcMessage};
+
+use crate::core::calls::{
+    CreateLinkRequest, CreateLinkResponse, DeviceDocmdRequest, DeviceDocmdResponse,
+    DeviceGenericRequest, DeviceReadRequest, DeviceReadResponse, DeviceReadStbResponse,
+    DeviceWriteRequest, DeviceWriteResponse,
+};
+use crate::core::client::*;
+use crate::rpc::{Client, Request};
+use crate::xdr::{Deserialize, Serialize};
+use crate::VxiErrorCode;
+
+/// Maximum size of the data of a `device_write` call announced to the client.
+const MAX_RECV_SIZE: u32 = 64 * 1024;
+
+/// A simulated instrument, handling the data transfer calls of a link.
+pub trait Device: Send {
+    /// Handle data written with `device_write`. `end` is set if the data is the end of a
+    /// message.
+    fn write(&mut self, data: &[u8], end: bool) -> Result<(), VxiErrorCode>;
+
+    /// Return at most `max` bytes of the pending response for `device_read` and whether
+    /// the end of the response is reached.
+    fn read(&mut self, max: usize) -> Result<(Vec<u8>, bool), VxiErrorCode>;
+
+    /// The status byte, returned by `device_readstb`.
+    fn status_byte(&mut self) -> u8 {
+        0
+    }
+
+    /// Handle `device_clear`.
+    fn clear(&mut self) {}
+}
+
+struct Inner<D> {
+    device: D,
+    xid: u32,
+    next_link: u32,
+    links: Vec<u32>,
+}
+
+/// Transport serving the VXI-11 core channel with a simulated [`Device`]. All links share
+/// the same device. Cloning a `SimClient` is cheap and shares the device.
+pub struct SimClient<D> {
+    inner: Arc<Mutex<Inner<D>>>,
+}
+
+impl<D> Clone for SimClient<D> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<D: Device> SimClient<D> {
+    pub fn new(device: D) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                device,
+                xid: 0,
+                next_link: 1,
+                links: Vec::new(),
+            })),
+        }
+    }
+
+    /// Run `f` with the device, e.g. to inspect or modify its state.
+    pub fn with_device<R>(&self, f: impl FnOnce(&mut D) -> R) -> R {
+        f(&mut self.inner.lock().unwrap().device)
+    }
+}
+
+impl<D: Device> Inner<D> {
+    /// Execute a call of the core channel. Returns `None` for unknown procedures.
+    fn execute(&mut self, procedure: u32, args: &[u8]) -> crate::Result<Option<Vec<u8>>> {
+        let mut out = Vec::new();
+        match procedure {
+            CALL_CREATE_LINK => {
+                CreateLinkRequest::deserialize(args)?;
+                let link_id = self.next_link;
+                self.next_link += 1;
+                self.links.push(link_id);
+                let resp = CreateLinkResponse {
+                    error: 0,
+                    link_id,
+                    port: 0,
+                    max_recv_size: MAX_RECV_SIZE,
+                };
+                resp.serialize(&mut out);
+            }
+            CALL_DESTROY_LINK => {
+                let link_id = u32::deserialize(args)?;
+                let error = match self.check_link(link_id) {
+                    Ok(()) => {
+                        self.links.retain(|x| *x != link_id);
+                        0
+                    }
+                    Err(err) => err.code(),
+                };
+                error.serialize(&mut out);
+            }
+            CALL_DEVICE_WRITE => {
+                let req = DeviceWriteRequest::deserialize(args)?;
+                let ret = self.check_link(req.link_id).and_then(|_| {
+                    let end = req.flags & OP_FLAG_END != 0;
+                    self.device.write(&req.data, end)
+                });
+                let resp = match ret {
+                    Ok(()) => DeviceWriteResponse {
+                        error: 0,
+                        size: req.data.len() as u32,
+                    },
+                    Err(err) => DeviceWriteResponse {
+                        error: err.code(),
+                        size: 0,
+                    },
+                };
+                resp.serialize(&mut out);
+            }
+            CALL_DEVICE_READ => {
+                let req = DeviceReadRequest::deserialize(args)?;
+                let max = req.request_size as usize;
+                let ret = self
+                    .check_link(req.link_id)
+                    .and_then(|_| self.device.read(max));
+                let resp = match ret {
+                    Ok((data, end)) => {
+                        let mut reason = ReadReason::default();
+                        if end {
+                            reason = reason | ReadReason::END;
+                        }
+                        if data.len() >= max {
+                            reason = reason | ReadReason::REQCNT;
+                        }
+                        DeviceReadResponse {
+                            error: 0,
+                            reason: reason.bits(),
+                            data: Bytes::from(data),
+                        }
+                    }
+                    Err(err) => DeviceReadResponse {
+                        error: err.code(),
+                        reason: 0,
+                        data: Bytes::new(),
+                    },
+                };
+                resp.serialize(&mut out);
+            }
+            CALL_DEVICE_READSTB => {
+                let req = DeviceGenericRequest::deserialize(args)?;
+                let resp = match self.check_link(req.link_id) {
+                    Ok(()) => DeviceReadStbResponse {
+                        error: 0,
+                        stb: self.device.status_byte() as u32,
+                    },
+                    Err(err) => DeviceReadStbResponse {
+                        error: err.code(),
+                        stb: 0,
+                    },
+                };
+                resp.serialize(&mut out);
+            }
+            CALL_DEVICE_CLEAR => {
+                let req = DeviceGenericRequest::deserialize(args)?;
+                let error = match self.check_link(req.link_id) {
+                    Ok(()) => {
+                        self.device.clear();
+                        0
+                    }
+                    Err(err) => err.code(),
+                };
+                error.serialize(&mut out);
+            }
+            CALL_DEVICE_DOCMD => {
+                DeviceDocmdRequest::deserialize(args)?;
+                let resp = DeviceDocmdResponse {
+                    error: VxiErrorCode::OperationNotSupported.code(),
+                    data_out: Vec::new(),
+                };
+                resp.serialize(&mut out);
+            }
+            _ => return Ok(None),
+        }
+        Ok(Some(out))
+    }
+
+    fn check_link(&self, link_id: u32) -> Result<(), VxiErrorCode> {
+        if self.links.contains(&link_id) {
+            Ok(())
+        } else {
+            Err(VxiErrorCode::InvalidLinkIdentifier)
+        }
+    }
+}
+
+#[async_trait]
+impl<D: Device> Client for SimClient<D> {
+    async fn call(&self, body: Request) -> crate::Result<Bytes> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.xid = inner.xid.wrapping_add(1);
+        let data;
+        let status = if body.program() != PROG || body.program_version() != VERS {
+            AcceptedStatus::ProgramUnavailable
+        } else {
+            match inner.execute(body.procedure(), body.payload()) {
+                Ok(Some(out)) => {
+                    data = out;
+                    AcceptedStatus::Success(&data[..])
+                }
+                Ok(None) => AcceptedStatus::ProcedureUnavailable,
+                Err(_) => AcceptedStatus::GarbageArgs,
+            }
+        };
+        let reply = AcceptedReply::new(AuthFlavor::AuthNone(None), status);
+        let msg = RpcMessage::<&[u8], &[u8]>::new(
+            inner.xid,
+            MessageType::Reply(ReplyBody::Accepted(reply)),
+        );
+        Ok(Bytes::from(msg.serialise().map_err(crate::Error::Io)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Connection, Error};
+
+    /// A device answering every message with the message in upper case.
+    #[derive(Default)]
+    struct Upper {
+        output: Vec<u8>,
+    }
+
+    impl Device for Upper {
+        fn write(&mut self, data: &[u8], _end: bool) -> Result<(), VxiErrorCode> {
+            self.output.extend(data.to_ascii_uppercase());
+            Ok(())
+        }
+
+        fn read(&mut self, max: usize) -> Result<(Vec<u8>, bool), VxiErrorCode> {
+            if self.output.is_empty() {
+                return Err(VxiErrorCode::IoTimeout);
+            }
+            let data: Vec<u8> = self.output.drain(..max.min(self.output.len())).collect();
+            Ok((data, self.output.is_empty()))
+        }
+    }
+
+    #[tokio::test]
+    async fn simulated_device() {
+        let client = SimClient::new(Upper::default());
+        let conn = Connection::from_client(client.clone());
+        let mut link = conn.create_link("inst0").await.unwrap();
+
+        link.device_write(b"hello".to_vec()).await.unwrap();
+        assert_eq!(link.device_read().await.unwrap(), b"HELLO");
+        let ret = link.device_read().await;
+        assert_eq!(
+            ret.unwrap_err().vxi_error_code(),
+            Some(VxiErrorCode::IoTimeout)
+        );
+
+        let other = conn.create_link("inst0").await.unwrap();
+        other.destroy_link().await.unwrap();
+        link.destroy_link().await.unwrap();
+        assert!(client.with_device(|x| x.output.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn unknown_program() {
+        let client = SimClient::new(Upper::default());
+        let ret: crate::Result<u32> = crate::rpc::call(&client, &0_u32, 1234, 1, 1).await;
+        assert!(matches!(ret, Err(Error::RpcProgramUnavailable)));
+    }
+}
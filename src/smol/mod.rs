@@ -0,0 +1,61 @@
+//! Connector for running the transport on `smol`.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+
+use async_trait::async_trait;
+use smol::net::TcpStream;
+
+use crate::stream::{self, Connector};
+
+/// [`Connector`] for `smol`, spawning on its global executor.
+pub struct Smol;
+
+#[async_trait]
+impl Connector for Smol {
+    type Stream = TcpStream;
+
+    async fn connect(addr: SocketAddr) -> io::Result<Self::Stream> {
+        TcpStream::connect(addr).await
+    }
+
+    fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) {
+        smol::spawn(future).detach();
+    }
+}
+
+/// A [`stream::TcpClient`] over a `smol` TCP stream.
+pub type TcpClient = stream::TcpClient<Smol>;
+
+#[cfg(test)]
+mod tests {
+    use smol::block_on;
+
+    use super::*;
+    use crate::testing::transport;
+
+    #[test]
+    fn concurrent_calls() {
+        let addr = transport::concurrent_calls_server();
+        block_on(async {
+            transport::concurrent_calls(TcpClient::connect(addr).await.unwrap()).await;
+        });
+    }
+
+    #[test]
+    fn connection_closed() {
+        let addr = transport::closing_server();
+        block_on(async {
+            transport::connection_closed(TcpClient::connect(addr).await.unwrap()).await;
+        });
+    }
+
+    #[test]
+    fn record_too_large() {
+        let addr = transport::oversized_reply_server();
+        block_on(async {
+            transport::record_too_large(TcpClient::connect(addr).await.unwrap()).await;
+        });
+    }
+}
@@ -0,0 +1,228 @@
+//! Support for the optional `metrics` feature.
+//!
+//! The following metrics are recorded with the `metrics` facade, labelled with the
+//! address of the server (`peer`) and the device name (`device`):
+//!
+//! - `vxi11_operations_total` (counter, labels `op` and `result`)
+//! - `vxi11_operation_duration_seconds` (histogram, label `op`)
+//! - `vxi11_bytes_written_total` and `vxi11_bytes_read_total` (counters)
+//! - `vxi11_remote_errors_total` (counter, labels `op` and `error`, see
+//!   [`crate::core::error::VxiErrorCode::name()`])
+//! - `vxi11_connects_total` (counter, `peer` only), connections established with
+//!   [`crate::Connection::connect()`]. The client does not reconnect on its own, thus
+//!   reconnects of an application show up as repeated connects to the same peer.
+//! - `vxi11_portmapper_duration_seconds` (histogram, `peer` only)
+//!
+//! Without the feature, all functions are no-ops.
+
+use std::time::Instant;
+
+use crate::core::client::CoreClient;
+use crate::rpc::Client;
+
+/// Measures a single operation on a link.
+pub(crate) struct Operation {
+    #[cfg(feature = "metrics")]
+    labels: Vec<metrics::Label>,
+    #[cfg(feature = "metrics")]
+    start: Instant,
+}
+
+impl Operation {
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    pub fn start<T: Client>(op: &'static str, link: &CoreClient<T>) -> Self {
+        #[cfg(feature = "metrics")]
+        {
+            let mut labels = link_labels(link);
+            labels.push(metrics::Label::new("op", op));
+            Self {
+                labels,
+                start: Instant::now(),
+            }
+        }
+        #[cfg(not(feature = "metrics"))]
+        Self {}
+    }
+
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    pub fn finish<R>(self, ret: &crate::Result<R>) {
+        #[cfg(feature = "metrics")]
+        {
+            let elapsed = self.start.elapsed().as_secs_f64();
+            metrics::histogram!("vxi11_operation_duration_seconds", self.labels.clone())
+                .record(elapsed);
+            let mut labels = self.labels.clone();
+            let result = if ret.is_ok() { "ok" } else { "error" };
+            labels.push(metrics::Label::new("result", result));
+            metrics::counter!("vxi11_operations_total", labels).increment(1);
+            if let Err(err) = ret {
+                if let Some(code) = err.vxi_error_code() {
+                    let mut labels = self.labels;
+                    labels.push(metrics::Label::new("error", code.name()));
+                    metrics::counter!("vxi11_remote_errors_total", labels).increment(1);
+                }
+            }
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn bytes_written<T: Client>(link: &CoreClient<T>, bytes: usize) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("vxi11_bytes_written_total", link_labels(link)).increment(bytes as u64);
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn bytes_read<T: Client>(link: &CoreClient<T>, bytes: usize) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("vxi11_bytes_read_total", link_labels(link)).increment(bytes as u64);
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn connected<T: Client>(client: &T) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("vxi11_connects_total", "peer" => peer(client)).increment(1);
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn portmapper_lookup<T: Client>(client: &T, start: Instant) {
+    #[cfg(feature = "metrics")]
+    metrics::histogram!("vxi11_portmapper_duration_seconds", "peer" => peer(client))
+        .record(start.elapsed().as_secs_f64());
+}
+
+#[cfg(feature = "metrics")]
+fn peer<T: Client>(client: &T) -> String {
+    match client.peer_addr() {
+        Some(addr) => addr.ip().to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn link_labels<T: Client>(link: &CoreClient<T>) -> Vec<metrics::Label> {
+    vec![
+        metrics::Label::new("peer", peer(link.connection().client())),
+        metrics::Label::new("device", link.device().to_string()),
+    ]
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use std::net::SocketAddr;
+
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use futures::executor::block_on;
+    use metrics::{SharedString, Unit};
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use metrics_util::CompositeKey;
+
+    use super::*;
+    use crate::rpc::Request;
+    use crate::scpi::Router;
+    use crate::sim::SimClient;
+    use crate::{Connection, Error};
+
+    type Snapshot = Vec<(CompositeKey, Option<Unit>, Option<SharedString>, DebugValue)>;
+
+    /// The value of the metric `name` with all of `labels`.
+    fn value<'a>(
+        snapshot: &'a Snapshot,
+        name: &str,
+        labels: &[(&str, &str)],
+    ) -> Option<&'a DebugValue> {
+        snapshot
+            .iter()
+            .find(|(key, _, _, _)| {
+                key.key().name() == name
+                    && labels.iter().all(|(k, v)| {
+                        key.key()
+                            .labels()
+                            .any(|label| label.key() == *k && label.value() == *v)
+                    })
+            })
+            .map(|(_, _, _, value)| value)
+    }
+
+    #[test]
+    fn link_operations() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || {
+            block_on(async {
+                let client = SimClient::new(Router::new("ACME", ()));
+                let mut link = Connection::from_client(client)
+                    .create_link("inst0")
+                    .await
+                    .unwrap();
+                link.device_write(b"*IDN?\n".to_vec()).await.unwrap();
+                link.device_read().await.unwrap();
+                link.device_read().await.unwrap_err();
+            })
+        });
+        let snapshot = snapshotter.snapshot().into_vec();
+
+        let ops = |op, result| {
+            let labels = [("device", "inst0"), ("op", op), ("result", result)];
+            value(&snapshot, "vxi11_operations_total", &labels)
+        };
+        assert_eq!(ops("device_write", "ok"), Some(&DebugValue::Counter(1)));
+        assert_eq!(ops("device_read", "ok"), Some(&DebugValue::Counter(1)));
+        assert_eq!(ops("device_read", "error"), Some(&DebugValue::Counter(1)));
+        let labels = [("op", "device_read"), ("error", "io_timeout")];
+        assert_eq!(
+            value(&snapshot, "vxi11_remote_errors_total", &labels),
+            Some(&DebugValue::Counter(1))
+        );
+
+        let labels = [("peer", "unknown"), ("device", "inst0")];
+        assert_eq!(
+            value(&snapshot, "vxi11_bytes_written_total", &labels),
+            Some(&DebugValue::Counter(6))
+        );
+        assert_eq!(
+            value(&snapshot, "vxi11_bytes_read_total", &labels),
+            Some(&DebugValue::Counter(5))
+        );
+
+        let labels = [("op", "device_read")];
+        match value(&snapshot, "vxi11_operation_duration_seconds", &labels) {
+            Some(DebugValue::Histogram(values)) => assert_eq!(values.len(), 2),
+            other => panic!("unexpected histogram: {:?}", other),
+        }
+    }
+
+    /// A client connected to a fixed peer.
+    #[derive(Clone)]
+    struct Peer;
+
+    #[async_trait]
+    impl Client for Peer {
+        async fn call(&self, _body: Request) -> crate::Result<Bytes> {
+            Err(Error::ConnectionClosed)
+        }
+
+        fn peer_addr(&self) -> Option<SocketAddr> {
+            Some(([192, 0, 2, 40], 1024).into())
+        }
+    }
+
+    #[test]
+    fn connects() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || {
+            for _ in 0..3 {
+                connected(&Peer);
+            }
+        });
+        let snapshot = snapshotter.snapshot().into_vec();
+
+        let labels = [("peer", "192.0.2.40")];
+        assert_eq!(
+            value(&snapshot, "vxi11_connects_total", &labels),
+            Some(&DebugValue::Counter(3))
+        );
+    }
+}
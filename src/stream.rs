@@ -0,0 +1,401 @@
+//! Runtime-agnostic RPC transport over any byte stream implementing the `futures-io`
+//! traits.
+//!
+//! [`TcpClient`] connects the transport over TCP with a [`Connector`] of an async runtime,
+//! e.g. [`crate::tokio::Tokio`].
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::io::Cursor;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::channel::oneshot;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use futures::lock::Mutex;
+use onc_rpc::{MessageType, RpcMessage};
+
+use crate::portmapper::{self, get_port};
+use crate::record::{fragment, read_record, RecordLimits};
+use crate::rpc::{message_xid, Client, Connect, Request};
+use crate::Error;
+
+/// A handle to an RPC connection over a byte stream `S`, e.g. a TCP stream or a
+/// TLS/SSH tunnelled stream.
+///
+/// Replies are received by a background future which dispatches them by their xid to the
+/// waiting calls. Thus, the handle may be cloned and used to perform multiple calls
+/// concurrently. The connection is closed once all handles are dropped.
+///
+/// If writing a request fails or a call is dropped while its request is written, the
+/// connection is closed and all pending calls fail, as the stream might be left in the
+/// middle of a record.
+pub struct StreamClient<S> {
+    inner: Arc<Inner<S>>,
+}
+
+impl<S> Clone for StreamClient<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+struct Inner<S> {
+    writer: Mutex<WriteHalf<S>>,
+    pending: Arc<std::sync::Mutex<Pending>>,
+    xid: AtomicU32,
+    limits: RecordLimits,
+    // dropping the sender stops the receiver
+    _shutdown: oneshot::Sender<()>,
+}
+
+#[derive(Default)]
+struct Pending {
+    calls: HashMap<u32, oneshot::Sender<crate::Result<Bytes>>>,
+    closed: bool,
+}
+
+/// Removes the pending call if the call is dropped before the reply arrived.
+struct PendingGuard<'a> {
+    pending: &'a std::sync::Mutex<Pending>,
+    xid: u32,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().calls.remove(&self.xid);
+    }
+}
+
+impl Pending {
+    /// Fail all pending calls with `err` and reject further calls.
+    fn close(&mut self, err: &Error) {
+        self.closed = true;
+        for (_, tx) in self.calls.drain() {
+            let err = match err {
+                Error::Io(err) => Error::Io(io::Error::new(err.kind(), err.to_string())),
+                Error::RecordTooLarge { size, limit } => Error::RecordTooLarge {
+                    size: *size,
+                    limit: *limit,
+                },
+                _ => Error::ConnectionClosed,
+            };
+            let _ = tx.send(Err(err));
+        }
+    }
+}
+
+/// Closes the connection if a record is not completely written, i.e. if the write fails
+/// or the call is dropped while writing. The stream is then out of sync and every other
+/// call on the connection would fail in unexpected ways.
+struct WriteGuard<'a> {
+    pending: &'a std::sync::Mutex<Pending>,
+    complete: bool,
+}
+
+impl Drop for WriteGuard<'_> {
+    fn drop(&mut self) {
+        if !self.complete {
+            log::debug!("Connection closed: incomplete record written");
+            self.pending.lock().unwrap().close(&Error::ConnectionClosed);
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Send + 'static> StreamClient<S> {
+    /// Create a client communicating over `stream`.
+    ///
+    /// Returns the client together with the future receiving the replies. The future
+    /// must be spawned on an executor and completes once the connection is closed or
+    /// all handles to the client have been dropped.
+    pub fn new(stream: S) -> (Self, impl Future<Output = ()> + Send + 'static) {
+        Self::with_limits(stream, RecordLimits::default())
+    }
+
+    /// Create a client communicating over `stream` with the given record size `limits`.
+    /// Refer to [`StreamClient::new()`].
+    pub fn with_limits(
+        stream: S,
+        limits: RecordLimits,
+    ) -> (Self, impl Future<Output = ()> + Send + 'static) {
+        let (reader, writer) = stream.split();
+        let pending = Arc::new(std::sync::Mutex::new(Pending::default()));
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let receiver = receive(reader, pending.clone(), limits);
+        let receiver = async move {
+            futures::pin_mut!(receiver);
+            futures::future::select(receiver, shutdown_rx).await;
+        };
+        let client = Self {
+            inner: Arc::new(Inner {
+                writer: Mutex::new(writer),
+                pending,
+                xid: AtomicU32::new(0),
+                limits,
+                _shutdown: shutdown_tx,
+            }),
+        };
+        (client, receiver)
+    }
+}
+
+#[async_trait]
+impl<S: AsyncRead + AsyncWrite + Send + 'static> Client for StreamClient<S> {
+    async fn call(&self, body: Request) -> crate::Result<Bytes> {
+        let xid = self
+            .inner
+            .xid
+            .fetch_add(1, Ordering::Relaxed)
+            .wrapping_add(1);
+
+        // construct a message and serialize
+        let msg = RpcMessage::new(xid, MessageType::Call(body));
+        let buf = Vec::with_capacity(msg.serialised_len() as usize);
+        let mut cursor = Cursor::new(buf);
+        msg.serialise_into(&mut cursor).map_err(Error::Io)?;
+        let data = fragment(cursor.into_inner(), &self.inner.limits);
+
+        // register the call before sending such that the reply cannot be missed
+        let (tx, rx) = oneshot::channel();
+        let _guard = {
+            let mut pending = self.inner.pending.lock().unwrap();
+            if pending.closed {
+                return Err(Error::ConnectionClosed);
+            }
+            pending.calls.insert(xid, tx);
+            PendingGuard {
+                pending: &self.inner.pending,
+                xid,
+            }
+        };
+
+        // send data out
+        {
+            let mut writer = self.inner.writer.lock().await;
+            if self.inner.pending.lock().unwrap().closed {
+                return Err(Error::ConnectionClosed);
+            }
+            let mut guard = WriteGuard {
+                pending: &self.inner.pending,
+                complete: false,
+            };
+            send_record(&mut *writer, &data).await.map_err(Error::Io)?;
+            guard.complete = true;
+        }
+
+        rx.await.map_err(|_| Error::ConnectionClosed)?
+    }
+}
+
+/// Opens TCP streams and spawns tasks on an async runtime.
+#[async_trait]
+pub trait Connector: Send + Sync + 'static {
+    type Stream: AsyncRead + AsyncWrite + Send + 'static;
+
+    /// Open a TCP stream to `addr`.
+    async fn connect(addr: SocketAddr) -> io::Result<Self::Stream>;
+
+    /// Run `future` in the background.
+    fn spawn<F: Future<Output = ()> + Send + 'static>(future: F);
+}
+
+/// A [`StreamClient`] over a TCP stream opened by the runtime connector `C`.
+pub struct TcpClient<C: Connector> {
+    client: StreamClient<C::Stream>,
+    peer: SocketAddr,
+}
+
+impl<C: Connector> Clone for TcpClient<C> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            peer: self.peer,
+        }
+    }
+}
+
+impl<C: Connector> TcpClient<C> {
+    pub async fn connect<T: Into<SocketAddr>>(addr: T) -> crate::Result<Self> {
+        Self::connect_with_limits(addr, RecordLimits::default()).await
+    }
+
+    /// Connect to `addr` with the given record size `limits`.
+    pub async fn connect_with_limits<T: Into<SocketAddr>>(
+        addr: T,
+        limits: RecordLimits,
+    ) -> crate::Result<Self> {
+        let peer = addr.into();
+        let stream = C::connect(peer).await.map_err(Error::Io)?;
+        let (client, receiver) = StreamClient::with_limits(stream, limits);
+        C::spawn(receiver);
+        Ok(Self { client, peer })
+    }
+}
+
+#[async_trait]
+impl<C: Connector> Client for TcpClient<C> {
+    async fn call(&self, body: Request) -> crate::Result<Bytes> {
+        self.client.call(body).await
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        Some(self.peer)
+    }
+}
+
+#[async_trait]
+impl<C: Connector> Connect for TcpClient<C> {
+    async fn connect_with_mapper<T: Into<IpAddr> + Send>(
+        addr: T,
+        prog: u32,
+        vers: u32,
+    ) -> crate::Result<Self> {
+        let addr = addr.into();
+        let mapper_client = Self::connect(SocketAddr::new(addr, portmapper::PORT)).await?;
+        let port = get_port(&mapper_client, prog, vers).await?;
+        Self::connect(SocketAddr::new(addr, port)).await
+    }
+}
+
+/// Receive replies and dispatch them to the pending calls until the connection fails.
+async fn receive<S: AsyncRead>(
+    mut reader: ReadHalf<S>,
+    pending: Arc<std::sync::Mutex<Pending>>,
+    limits: RecordLimits,
+) {
+    let err = loop {
+        let reply = match read_record(&mut reader, &limits).await {
+            Ok(reply) => reply,
+            Err(err) => break err,
+        };
+        let xid = match message_xid(&reply) {
+            Some(xid) => xid,
+            None => continue,
+        };
+        match pending.lock().unwrap().calls.remove(&xid) {
+            Some(tx) => {
+                let _ = tx.send(Ok(reply));
+            }
+            None => log::debug!("Dropping reply with unexpected xid: {}", xid),
+        }
+    };
+    log::debug!("Connection closed: {}", err);
+    pending.lock().unwrap().close(&err);
+}
+
+async fn send_record<T: AsyncWrite + Unpin>(sock: &mut T, data: &[u8]) -> io::Result<()> {
+    sock.write_all(data).await?;
+    sock.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use futures::FutureExt;
+    use onc_rpc::auth::AuthFlavor;
+    use onc_rpc::CallBody;
+
+    use super::*;
+
+    /// A stream accepting `accept` bytes, then failing if `fail` is set or stalling.
+    /// Reads never complete.
+    struct Stalling {
+        accept: usize,
+        fail: bool,
+    }
+
+    impl AsyncRead for Stalling {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            _: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Pending
+        }
+    }
+
+    impl AsyncWrite for Stalling {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            if self.accept > 0 {
+                let len = buf.len().min(self.accept);
+                self.accept -= len;
+                Poll::Ready(Ok(len))
+            } else if self.fail {
+                Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()))
+            } else {
+                Poll::Pending
+            }
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn request() -> Request {
+        CallBody::new(
+            1,
+            1,
+            1,
+            AuthFlavor::AuthNone(None),
+            AuthFlavor::AuthNone(None),
+            Vec::new(),
+        )
+    }
+
+    #[test]
+    fn dropped_partial_write_closes_connection() {
+        let (client, _receiver) = StreamClient::new(Stalling {
+            accept: 4,
+            fail: false,
+        });
+
+        let mut first = client.call(request());
+        let mut second = client.call(request());
+        assert!((&mut first).now_or_never().is_none());
+        assert!((&mut second).now_or_never().is_none());
+        drop(first);
+
+        assert!(matches!(
+            second.now_or_never(),
+            Some(Err(Error::ConnectionClosed))
+        ));
+        assert!(matches!(
+            client.call(request()).now_or_never(),
+            Some(Err(Error::ConnectionClosed))
+        ));
+    }
+
+    #[test]
+    fn failed_write_closes_connection() {
+        let (client, _receiver) = StreamClient::new(Stalling {
+            accept: 4,
+            fail: true,
+        });
+
+        assert!(matches!(
+            client.call(request()).now_or_never(),
+            Some(Err(Error::Io(_)))
+        ));
+        assert!(matches!(
+            client.call(request()).now_or_never(),
+            Some(Err(Error::ConnectionClosed))
+        ));
+    }
+}
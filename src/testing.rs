@@ -0,0 +1,328 @@
+//! Helpers shared by the unit tests.
+
+use bytes::Bytes;
+use onc_rpc::auth::AuthFlavor;
+use onc_rpc::{AcceptedReply, AcceptedStatus, MessageType, ReplyBody, RpcMessage};
+
+/// Build a reply message for a successful call.
+pub(crate) fn reply(xid: u32, payload: &[u8]) -> Bytes {
+    let reply = AcceptedReply::new(AuthFlavor::AuthNone(None), AcceptedStatus::Success(payload));
+    let msg = RpcMessage::<&[u8], &[u8]>::new(xid, MessageType::Reply(ReplyBody::Accepted(reply)));
+    Bytes::from(msg.serialise().unwrap())
+}
+
+/// An RPC server for the TCP transports.
+#[cfg(any(
+    feature = "tokio",
+    feature = "async-std",
+    feature = "smol",
+    feature = "blocking"
+))]
+mod server {
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::thread;
+
+    use onc_rpc::RpcMessage;
+
+    use super::reply;
+
+    /// A call received by a [`MockServer`].
+    pub(crate) struct ReceivedCall {
+        pub xid: u32,
+        pub procedure: u32,
+    }
+
+    /// A connection accepted by a [`MockServer`].
+    pub(crate) struct ServerConnection {
+        stream: TcpStream,
+    }
+
+    impl ServerConnection {
+        pub fn recv(&mut self) -> ReceivedCall {
+            let mut header = [0_u8; 4];
+            self.stream.read_exact(&mut header).unwrap();
+            let len = u32::from_be_bytes(header) & 0x7fffffff;
+            let mut record = header.to_vec();
+            record.resize(len as usize + 4, 0);
+            self.stream.read_exact(&mut record[4..]).unwrap();
+            let msg = RpcMessage::from_bytes(&record).unwrap();
+            let call = msg.call_body().unwrap();
+            ReceivedCall {
+                xid: msg.xid(),
+                procedure: call.procedure(),
+            }
+        }
+
+        pub fn reply(&mut self, xid: u32, payload: &[u8]) {
+            self.stream.write_all(&reply(xid, payload)).unwrap();
+        }
+
+        #[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+        pub fn send_raw(&mut self, data: &[u8]) {
+            self.stream.write_all(data).unwrap();
+        }
+    }
+
+    /// An RPC server running on a separate thread serving a single connection.
+    pub(crate) struct MockServer;
+
+    impl MockServer {
+        /// Start listening and run `f` with the first accepted connection.
+        pub fn spawn<F: FnOnce(ServerConnection) + Send + 'static>(f: F) -> SocketAddr {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            thread::spawn(move || {
+                let (stream, _) = listener.accept().unwrap();
+                f(ServerConnection { stream })
+            });
+            addr
+        }
+    }
+}
+
+#[cfg(any(
+    feature = "tokio",
+    feature = "async-std",
+    feature = "smol",
+    feature = "blocking"
+))]
+pub(crate) use server::MockServer;
+
+/// Tests run against the transport of every runtime.
+#[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+pub(crate) mod transport {
+    use std::net::SocketAddr;
+
+    use onc_rpc::auth::AuthFlavor;
+    use onc_rpc::CallBody;
+
+    use super::MockServer;
+    use crate::rpc::{decode_reply, Client, Request};
+    use crate::Error;
+
+    fn request(procedure: u32) -> Request {
+        CallBody::new(
+            1,
+            1,
+            procedure,
+            AuthFlavor::AuthNone(None),
+            AuthFlavor::AuthNone(None),
+            Vec::new(),
+        )
+    }
+
+    /// Start a server which waits for two calls and replies in reverse order.
+    pub fn concurrent_calls_server() -> SocketAddr {
+        MockServer::spawn(|mut conn| {
+            let first = conn.recv();
+            let second = conn.recv();
+            conn.reply(second.xid, &second.procedure.to_be_bytes());
+            conn.reply(first.xid, &first.procedure.to_be_bytes());
+        })
+    }
+
+    pub async fn concurrent_calls<C: Client>(client: C) {
+        let other = client.clone();
+        let (first, second) = futures::join!(client.call(request(1)), other.call(request(2)));
+        assert_eq!(decode_reply(&first.unwrap()).unwrap(), 1_u32.to_be_bytes());
+        assert_eq!(decode_reply(&second.unwrap()).unwrap(), 2_u32.to_be_bytes());
+    }
+
+    /// Start a server which closes the connection after receiving a call.
+    pub fn closing_server() -> SocketAddr {
+        MockServer::spawn(|mut conn| {
+            conn.recv();
+        })
+    }
+
+    /// Start a server which announces a huge reply.
+    pub fn oversized_reply_server() -> SocketAddr {
+        MockServer::spawn(|mut conn| {
+            conn.recv();
+            conn.send_raw(&0xffff_ffff_u32.to_be_bytes());
+        })
+    }
+
+    pub async fn record_too_large<C: Client>(client: C) {
+        assert!(matches!(
+            client.call(request(1)).await,
+            Err(Error::RecordTooLarge { .. })
+        ));
+    }
+
+    pub async fn connection_closed<C: Client>(client: C) {
+        assert!(matches!(client.call(request(1)).await, Err(Error::Io(_))));
+        assert!(matches!(
+            client.call(request(1)).await,
+            Err(Error::ConnectionClosed)
+        ));
+    }
+}
+
+/// A fake VXI-11 instrument used as in-process transport.
+pub(crate) mod device {
+    use std::sync::{Arc, Mutex, MutexGuard};
+
+    use async_trait::async_trait;
+    use bytes::Bytes;
+
+    use super::reply;
+    use crate::core::calls::CreateLinkRequest;
+    use crate::core::client::*;
+    use crate::rpc::{Client, Request};
+    use crate::xdr::{Decoder, Encoder};
+
+    /// Handler for `device_docmd`, returning `data_out` for a command and its `data_in`.
+    pub(crate) type DocmdHandler = Box<dyn FnMut(u32, &[u8]) -> Vec<u8> + Send>;
+
+    /// A `device_docmd` call received by a [`MockDevice`].
+    #[derive(Debug, PartialEq, Eq)]
+    pub(crate) struct Docmd {
+        pub cmd: u32,
+        pub network_order: bool,
+        pub data_size: u32,
+        pub data_in: Vec<u8>,
+    }
+
+    /// A fake instrument which accepts at most `accept` bytes per `device_write` call
+    /// and answers reads from `output`, or with empty chunks without reason if `stalled`.
+    /// `device_docmd` calls are answered by `on_docmd` or, by default, with the command
+    /// followed by the data in reversed order.
+    pub(crate) struct MockDevice {
+        pub xid: u32,
+        pub links: u32,
+        /// The device names of the links created.
+        pub devices: Vec<String>,
+        /// The `lock_device` flags and lock timeouts of the links created.
+        pub link_locks: Vec<(bool, u32)>,
+        pub accept: usize,
+        pub max_recv_size: u32,
+        pub received: Vec<u8>,
+        pub flags: Vec<u32>,
+        pub output: Vec<u8>,
+        pub read_sizes: Vec<u32>,
+        /// The IO and lock timeouts of the `device_read` calls.
+        pub read_timeouts: Vec<(u32, u32)>,
+        pub stalled: bool,
+        pub stb: u8,
+        pub docmds: Vec<Docmd>,
+        pub on_docmd: Option<DocmdHandler>,
+    }
+
+    impl MockDevice {
+        pub fn new(accept: usize, max_recv_size: u32) -> Self {
+            Self {
+                xid: 0,
+                links: 0,
+                devices: Vec::new(),
+                link_locks: Vec::new(),
+                accept,
+                max_recv_size,
+                received: Vec::new(),
+                flags: Vec::new(),
+                output: Vec::new(),
+                read_sizes: Vec::new(),
+                read_timeouts: Vec::new(),
+                stalled: false,
+                stb: 0,
+                docmds: Vec::new(),
+                on_docmd: None,
+            }
+        }
+    }
+
+    #[derive(Clone)]
+    pub(crate) struct MockClient(Arc<Mutex<MockDevice>>);
+
+    impl MockClient {
+        pub fn new(device: MockDevice) -> Self {
+            Self(Arc::new(Mutex::new(device)))
+        }
+
+        pub fn device(&self) -> MutexGuard<'_, MockDevice> {
+            self.0.lock().unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl Client for MockClient {
+        async fn call(&self, body: Request) -> crate::Result<Bytes> {
+            let mut this = self.device();
+            this.xid += 1;
+            let mut out = Vec::new();
+            let mut enc = Encoder::new(&mut out);
+            let mut args = Decoder::new(body.payload());
+            match body.procedure() {
+                CALL_CREATE_LINK => {
+                    let request: CreateLinkRequest = args.decode().unwrap();
+                    this.devices.push(request.device);
+                    this.link_locks
+                        .push((request.lock, request.lock_timeout_ms));
+                    this.links += 1;
+                    for x in [0, this.links, 1234, this.max_recv_size] {
+                        enc.u32(x);
+                    }
+                }
+                CALL_DEVICE_WRITE => {
+                    let [_, _, _, flags]: [u32; 4] = args.decode().unwrap();
+                    let data = args.opaque().unwrap();
+                    let size = data.len().min(this.accept);
+                    this.received.extend_from_slice(&data[..size]);
+                    this.flags.push(flags);
+                    enc.u32(0);
+                    enc.u32(size as u32);
+                }
+                CALL_DEVICE_READ => {
+                    let [_, request_size, io_timeout, lock_timeout]: [u32; 4] =
+                        args.decode().unwrap();
+                    this.read_sizes.push(request_size);
+                    this.read_timeouts.push((io_timeout, lock_timeout));
+                    let mut data = Vec::new();
+                    let mut reason = 0;
+                    if !this.stalled {
+                        let size = this.output.len().min(request_size as usize);
+                        data = this.output.drain(..size).collect();
+                        if size == request_size as usize {
+                            reason |= ReadReason::REQCNT.bits();
+                        }
+                        if this.output.is_empty() {
+                            reason |= ReadReason::END.bits();
+                        }
+                    }
+                    enc.u32(0);
+                    enc.u32(reason);
+                    enc.opaque(&data);
+                }
+                CALL_DEVICE_DOCMD => {
+                    let [_, _, _, _, cmd]: [u32; 5] = args.decode().unwrap();
+                    let network_order = args.bool().unwrap();
+                    let data_size = args.u32().unwrap();
+                    let data_in = args.opaque().unwrap().to_vec();
+                    let data_out = match this.on_docmd.as_mut() {
+                        Some(handler) => handler(cmd, &data_in),
+                        None => {
+                            let mut data_out = cmd.to_be_bytes().to_vec();
+                            data_out.extend(data_in.iter().rev());
+                            data_out
+                        }
+                    };
+                    this.docmds.push(Docmd {
+                        cmd,
+                        network_order,
+                        data_size,
+                        data_in,
+                    });
+                    enc.u32(0);
+                    enc.opaque(&data_out);
+                }
+                CALL_DEVICE_READSTB => {
+                    enc.u32(0);
+                    enc.u32(this.stb as u32);
+                }
+                procedure => panic!("unexpected procedure {}", procedure),
+            }
+            Ok(reply(this.xid, &out))
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Support for the optional `tracing` feature.
+//!
+//! Spans are created with the [`link_span!`] and [`rpc_span!`] macros. Without the
+//! feature, they evaluate to a no-op [`Span`], such that the instrumented code does not
+//! need to be feature gated.
+
+use std::future::Future;
+
+use crate::Error;
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::Span;
+
+/// Stand-in for `tracing::Span` if the `tracing` feature is disabled.
+#[cfg(not(feature = "tracing"))]
+#[derive(Clone)]
+pub(crate) struct Span;
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    pub fn record<V>(&self, _field: &str, _value: V) -> &Self {
+        self
+    }
+}
+
+/// Open a span for an operation on a link, i.e. a [`crate::CoreClient`].
+macro_rules! link_span {
+    ($name:literal, $link:expr) => {{
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            $name,
+            link_id = $link.link_id(),
+            device = $link.device(),
+            peer = ?$link.connection().client().peer_addr(),
+            bytes = tracing::field::Empty,
+            reason = tracing::field::Empty,
+            error = tracing::field::Empty,
+            error_code = tracing::field::Empty,
+        );
+        #[cfg(not(feature = "tracing"))]
+        let span = crate::trace::Span;
+        span
+    }};
+}
+
+/// Open a span for a single RPC call.
+macro_rules! rpc_span {
+    ($prog:expr, $vers:expr, $proc:expr) => {{
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "rpc",
+            prog = $prog,
+            vers = $vers,
+            proc = $proc,
+            xid = tracing::field::Empty,
+            latency_us = tracing::field::Empty,
+            error = tracing::field::Empty,
+        );
+        #[cfg(not(feature = "tracing"))]
+        let span = crate::trace::Span;
+        span
+    }};
+}
+
+pub(crate) use {link_span, rpc_span};
+
+/// Run `fut` within `span` and record the error of a failed operation.
+pub(crate) async fn instrument<F, R>(span: &Span, fut: F) -> crate::Result<R>
+where
+    F: Future<Output = crate::Result<R>>,
+{
+    #[cfg(feature = "tracing")]
+    let ret = tracing::Instrument::instrument(fut, span.clone()).await;
+    #[cfg(not(feature = "tracing"))]
+    let ret = fut.await;
+    if let Err(err) = &ret {
+        record_error(span, err);
+    }
+    ret
+}
+
+fn record_error(span: &Span, err: &Error) {
+    span.record("error", err.to_string().as_str());
+    if let Error::VxiRemoteError(code) = err {
+        span.record("error_code", *code);
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::collections::HashMap;
+    use std::fmt::Debug;
+    use std::sync::{Arc, Mutex};
+
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::Layer;
+
+    use crate::scpi::Router;
+    use crate::sim::SimClient;
+    use crate::{Connection, VxiErrorCode};
+
+    /// The name and the recorded fields of a span.
+    type Recorded = (&'static str, HashMap<&'static str, String>);
+
+    /// A layer recording the fields of all spans.
+    #[derive(Clone, Default)]
+    struct Spans(Arc<Mutex<HashMap<Id, Recorded>>>);
+
+    struct Fields<'a>(&'a mut HashMap<&'static str, String>);
+
+    impl Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0.insert(field.name(), format!("{:?}", value));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name(), value.to_string());
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Spans {
+        fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, _ctx: Context<'_, S>) {
+            let mut fields = HashMap::new();
+            attrs.record(&mut Fields(&mut fields));
+            let name = attrs.metadata().name();
+            self.0.lock().unwrap().insert(id.clone(), (name, fields));
+        }
+
+        fn on_record(&self, id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+            if let Some((_, fields)) = self.0.lock().unwrap().get_mut(id) {
+                values.record(&mut Fields(fields));
+            }
+        }
+    }
+
+    impl Spans {
+        fn named(&self, name: &str) -> Vec<HashMap<&'static str, String>> {
+            let spans = self.0.lock().unwrap();
+            let mut ret: Vec<_> = spans
+                .iter()
+                .filter(|(_, (x, _))| *x == name)
+                .map(|(id, (_, fields))| (id.into_u64(), fields.clone()))
+                .collect();
+            ret.sort_by_key(|(id, _)| *id);
+            ret.into_iter().map(|(_, fields)| fields).collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn recorded_fields() {
+        let spans = Spans::default();
+        let subscriber = tracing_subscriber::registry().with(spans.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let client = SimClient::new(Router::new("ACME", ()));
+        let mut link = Connection::from_client(client)
+            .create_link("inst0")
+            .await
+            .unwrap();
+        link.device_write(b"*IDN?\n".to_vec()).await.unwrap();
+        link.device_read().await.unwrap();
+        link.device_read().await.unwrap_err();
+
+        let writes = spans.named("device_write");
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0]["link_id"], "1");
+        assert_eq!(writes[0]["device"], "inst0");
+        assert_eq!(writes[0]["bytes"], "6");
+        assert!(!writes[0].contains_key("error"));
+
+        let reads = spans.named("device_read");
+        assert_eq!(reads.len(), 2);
+        assert_eq!(reads[0]["bytes"], "5");
+        let code = VxiErrorCode::IoTimeout.code().to_string();
+        assert_eq!(reads[1]["error_code"], code);
+        assert!(reads[1].contains_key("error"));
+
+        // create_link, device_write and two device_read calls
+        let calls = spans.named("rpc");
+        assert_eq!(calls.len(), 4);
+        for (i, call) in calls.iter().enumerate() {
+            assert_eq!(call["prog"], "395183");
+            assert_eq!(call["xid"], (i + 1).to_string());
+            assert!(call["latency_us"].parse::<u64>().is_ok());
+        }
+    }
+}
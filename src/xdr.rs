@@ -0,0 +1,538 @@
+//! XDR encoding and decoding ([RFC 4506]).
+//!
+//! Types are encoded with [`Serialize`] into a byte buffer and decoded with
+//! [`Deserialize`] from a [`Decoder`], which keeps track of the current offset. Decoding
+//! errors report the offset at which the data is short or malformed.
+//!
+//! | XDR                          | Rust                          |
+//! |------------------------------|-------------------------------|
+//! | int, unsigned int            | `i32`, `u32`                  |
+//! | hyper, unsigned hyper        | `i64`, `u64`                  |
+//! | bool                         | `bool`                        |
+//! | enum, discriminated union    | enums with `#[derive]`        |
+//! | fixed-length opaque          | `[u8; N]`                     |
+//! | variable-length opaque       | `Vec<u8>`                     |
+//! | string                       | `String`                      |
+//! | fixed-length array           | `[T; N]` with `T: Default`    |
+//! | variable-length array        | `Vec<T>`                      |
+//! | optional-data                | `Option<T>`                   |
+//! | void                         | `()`                          |
+//!
+//! [RFC 4506]: https://tools.ietf.org/html/rfc4506
+
+use std::fmt;
+
+use bytes::Bytes;
+
+use crate::Error;
+
+/// Derive [`Serialize`] for structs and enums, see [`async_vxi11_derive`].
+#[cfg(feature = "derive")]
+pub use async_vxi11_derive::XdrSerialize;
+
+/// Derive [`Deserialize`] for structs and enums, see [`async_vxi11_derive`].
+#[cfg(feature = "derive")]
+pub use async_vxi11_derive::XdrDeserialize;
+
+/// Trait for XDR serialization.
+pub trait Serialize {
+    fn serialize(&self, out: &mut Vec<u8>);
+}
+
+/// Trait for XDR deserialization.
+///
+/// Decoding fails with [`Error::Xdr`] if the data is too short or malformed, it never
+/// panics.
+pub trait Deserialize
+where
+    Self: Sized,
+{
+    /// A lower bound of the encoded size in bytes, 0 if unknown.
+    const MIN_SIZE: usize = 0;
+
+    /// Decode a value at the current position of `decoder`.
+    fn decode(decoder: &mut Decoder<'_>) -> crate::Result<Self>;
+
+    fn deserialize(data: &[u8]) -> crate::Result<Self> {
+        Self::decode(&mut Decoder::new(data))
+    }
+
+    /// Deserialize from the start of `data` and return the number of bytes consumed.
+    fn deserialize_partial(data: &[u8]) -> crate::Result<(Self, usize)> {
+        let mut decoder = Decoder::new(data);
+        let ret = Self::decode(&mut decoder)?;
+        Ok((ret, decoder.offset()))
+    }
+
+    /// Deserialize from the received reply `data`. Types containing opaque data may
+    /// override this to return slices of `data` instead of copies.
+    fn deserialize_bytes(data: &Bytes) -> crate::Result<Self> {
+        Self::deserialize(data)
+    }
+}
+
+/// Reason why XDR data cannot be decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum DecodeErrorKind {
+    #[error("{needed} bytes expected but only {available} left")]
+    UnexpectedEnd { needed: usize, available: usize },
+    #[error("invalid boolean {0}")]
+    InvalidBool(u32),
+    #[error("string is not valid UTF-8")]
+    InvalidUtf8,
+    #[error("unknown discriminant {0}")]
+    InvalidDiscriminant(i32),
+}
+
+/// An error decoding XDR data, with the offset at which it occurred.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    pub offset: usize,
+    pub kind: DecodeErrorKind,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.kind, self.offset)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Number of padding bytes following `len` bytes of opaque data.
+fn padding(len: usize) -> usize {
+    (4 - len % 4) % 4
+}
+
+/// Appends XDR items to a buffer.
+pub struct Encoder<'a> {
+    out: &'a mut Vec<u8>,
+}
+
+impl<'a> Encoder<'a> {
+    pub fn new(out: &'a mut Vec<u8>) -> Self {
+        Self { out }
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.out.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn i32(&mut self, value: i32) {
+        self.out.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.out.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn i64(&mut self, value: i64) {
+        self.out.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u32(value as u32);
+    }
+
+    /// Fixed-length opaque data, without length prefix.
+    pub fn fixed_opaque(&mut self, data: &[u8]) {
+        self.out.extend_from_slice(data);
+        self.out.resize(self.out.len() + padding(data.len()), 0);
+    }
+
+    /// Variable-length opaque data, prefixed with its length.
+    pub fn opaque(&mut self, data: &[u8]) {
+        self.u32(data.len() as u32);
+        self.fixed_opaque(data);
+    }
+
+    pub fn string(&mut self, value: &str) {
+        self.opaque(value.as_bytes());
+    }
+
+    pub fn encode<T: Serialize + ?Sized>(&mut self, value: &T) {
+        value.serialize(self.out);
+    }
+}
+
+/// Decodes XDR items from a buffer, starting at its beginning.
+pub struct Decoder<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    /// Number of bytes decoded so far.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Number of bytes left to decode.
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.offset
+    }
+
+    /// An error of the given kind at `offset`.
+    pub fn error(&self, offset: usize, kind: DecodeErrorKind) -> Error {
+        Error::Xdr(DecodeError { offset, kind })
+    }
+
+    fn take(&mut self, len: usize) -> crate::Result<&'a [u8]> {
+        if len > self.remaining() {
+            let kind = DecodeErrorKind::UnexpectedEnd {
+                needed: len,
+                available: self.remaining(),
+            };
+            return Err(self.error(self.offset, kind));
+        }
+        let ret = &self.data[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(ret)
+    }
+
+    fn array<const N: usize>(&mut self) -> crate::Result<[u8; N]> {
+        let mut ret = [0_u8; N];
+        ret.copy_from_slice(self.take(N)?);
+        Ok(ret)
+    }
+
+    pub fn u32(&mut self) -> crate::Result<u32> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    pub fn i32(&mut self) -> crate::Result<i32> {
+        Ok(i32::from_be_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> crate::Result<u64> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+
+    pub fn i64(&mut self) -> crate::Result<i64> {
+        Ok(i64::from_be_bytes(self.array()?))
+    }
+
+    pub fn bool(&mut self) -> crate::Result<bool> {
+        let offset = self.offset;
+        match self.u32()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            x => Err(self.error(offset, DecodeErrorKind::InvalidBool(x))),
+        }
+    }
+
+    /// Fixed-length opaque data of `len` bytes, skipping its padding.
+    pub fn fixed_opaque(&mut self, len: usize) -> crate::Result<&'a [u8]> {
+        let ret = self.take(len)?;
+        self.take(padding(len))?;
+        Ok(ret)
+    }
+
+    /// Variable-length opaque data, prefixed with its length.
+    pub fn opaque(&mut self) -> crate::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.fixed_opaque(len)
+    }
+
+    pub fn string(&mut self) -> crate::Result<&'a str> {
+        let offset = self.offset;
+        let data = self.opaque()?;
+        std::str::from_utf8(data).map_err(|_| self.error(offset, DecodeErrorKind::InvalidUtf8))
+    }
+
+    pub fn decode<T: Deserialize>(&mut self) -> crate::Result<T> {
+        T::decode(self)
+    }
+}
+
+macro_rules! impl_primitive {
+    ($($ty:ident: $size:expr),*) => {$(
+        impl Serialize for $ty {
+            fn serialize(&self, out: &mut Vec<u8>) {
+                Encoder::new(out).$ty(*self);
+            }
+        }
+
+        impl Deserialize for $ty {
+            const MIN_SIZE: usize = $size;
+
+            fn decode(decoder: &mut Decoder<'_>) -> crate::Result<Self> {
+                decoder.$ty()
+            }
+        }
+    )*};
+}
+
+impl_primitive!(u32: 4, i32: 4, u64: 8, i64: 8, bool: 4);
+
+impl Serialize for () {
+    fn serialize(&self, _out: &mut Vec<u8>) {}
+}
+
+impl Deserialize for () {
+    fn decode(_decoder: &mut Decoder<'_>) -> crate::Result<Self> {
+        Ok(())
+    }
+}
+
+impl Serialize for str {
+    fn serialize(&self, out: &mut Vec<u8>) {
+        Encoder::new(out).string(self);
+    }
+}
+
+impl Serialize for String {
+    fn serialize(&self, out: &mut Vec<u8>) {
+        Encoder::new(out).string(self);
+    }
+}
+
+impl Deserialize for String {
+    const MIN_SIZE: usize = 4;
+
+    fn decode(decoder: &mut Decoder<'_>) -> crate::Result<Self> {
+        Ok(decoder.string()?.to_string())
+    }
+}
+
+impl Serialize for Vec<u8> {
+    fn serialize(&self, out: &mut Vec<u8>) {
+        Encoder::new(out).opaque(self);
+    }
+}
+
+impl Deserialize for Vec<u8> {
+    const MIN_SIZE: usize = 4;
+
+    fn decode(decoder: &mut Decoder<'_>) -> crate::Result<Self> {
+        Ok(decoder.opaque()?.to_vec())
+    }
+}
+
+impl<const N: usize> Serialize for [u8; N] {
+    fn serialize(&self, out: &mut Vec<u8>) {
+        Encoder::new(out).fixed_opaque(self);
+    }
+}
+
+impl<const N: usize> Deserialize for [u8; N] {
+    const MIN_SIZE: usize = (N + 3) & !3;
+
+    fn decode(decoder: &mut Decoder<'_>) -> crate::Result<Self> {
+        let mut ret = [0_u8; N];
+        ret.copy_from_slice(decoder.fixed_opaque(N)?);
+        Ok(ret)
+    }
+}
+
+impl<T: Serialize> Serialize for Vec<T> {
+    fn serialize(&self, out: &mut Vec<u8>) {
+        Encoder::new(out).u32(self.len() as u32);
+        for item in self {
+            item.serialize(out);
+        }
+    }
+}
+
+impl<T: Deserialize> Deserialize for Vec<T> {
+    const MIN_SIZE: usize = 4;
+
+    fn decode(decoder: &mut Decoder<'_>) -> crate::Result<Self> {
+        let offset = decoder.offset();
+        let len = decoder.u32()? as usize;
+        // reject lengths the input cannot hold before allocating
+        let available = decoder.remaining();
+        let needed = len.saturating_mul(T::MIN_SIZE);
+        if needed > available {
+            let kind = DecodeErrorKind::UnexpectedEnd { needed, available };
+            return Err(decoder.error(offset, kind));
+        }
+        // elements of unknown size take at least 4 bytes each if they have any data
+        let mut ret = Vec::with_capacity(len.min(available / T::MIN_SIZE.max(4)));
+        for _ in 0..len {
+            ret.push(decoder.decode()?);
+        }
+        Ok(ret)
+    }
+}
+
+impl<T: Serialize, const N: usize> Serialize for [T; N] {
+    fn serialize(&self, out: &mut Vec<u8>) {
+        for item in self {
+            item.serialize(out);
+        }
+    }
+}
+
+/// The elements are decoded in place, thus they must implement [`Default`].
+impl<T: Deserialize + Default, const N: usize> Deserialize for [T; N] {
+    const MIN_SIZE: usize = N * T::MIN_SIZE;
+
+    fn decode(decoder: &mut Decoder<'_>) -> crate::Result<Self> {
+        let mut ret: [T; N] = std::array::from_fn(|_| T::default());
+        for item in &mut ret {
+            *item = decoder.decode()?;
+        }
+        Ok(ret)
+    }
+}
+
+impl<T: Serialize> Serialize for Option<T> {
+    fn serialize(&self, out: &mut Vec<u8>) {
+        Encoder::new(out).bool(self.is_some());
+        if let Some(x) = self {
+            x.serialize(out);
+        }
+    }
+}
+
+impl<T: Deserialize> Deserialize for Option<T> {
+    const MIN_SIZE: usize = 4;
+
+    fn decode(decoder: &mut Decoder<'_>) -> crate::Result<Self> {
+        if decoder.bool()? {
+            Ok(Some(decoder.decode()?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_vxi11_derive::{XdrDeserialize, XdrSerialize};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, XdrSerialize, XdrDeserialize)]
+    struct Record {
+        id: u32,
+        offset: i32,
+        valid: bool,
+        name: String,
+        data: Vec<u8>,
+        tag: [u8; 3],
+        range: [u32; 2],
+        next: Option<u32>,
+        kind: Kind,
+    }
+
+    #[derive(Debug, PartialEq, XdrSerialize, XdrDeserialize)]
+    enum Kind {
+        Empty,
+        #[xdr(case = 5)]
+        Value(u32),
+        #[xdr(case = -1)]
+        Error {
+            code: u32,
+            message: String,
+        },
+    }
+
+    #[derive(Debug, PartialEq, XdrSerialize, XdrDeserialize)]
+    struct Pair<T> {
+        first: T,
+        second: Option<T>,
+    }
+
+    #[test]
+    fn derive_roundtrip() {
+        let record = Record {
+            id: 1,
+            offset: -2,
+            valid: true,
+            name: "ab".to_string(),
+            data: vec![1, 2, 3, 4, 5],
+            tag: *b"xyz",
+            range: [3, 4],
+            next: Some(6),
+            kind: Kind::Error {
+                code: 7,
+                message: "c".to_string(),
+            },
+        };
+        let mut data = Vec::new();
+        record.serialize(&mut data);
+        #[rustfmt::skip]
+        assert_eq!(data, [
+            0, 0, 0, 1, 0xff, 0xff, 0xff, 0xfe, 0, 0, 0, 1,
+            0, 0, 0, 2, b'a', b'b', 0, 0,
+            0, 0, 0, 5, 1, 2, 3, 4, 5, 0, 0, 0,
+            b'x', b'y', b'z', 0,
+            0, 0, 0, 3, 0, 0, 0, 4,
+            0, 0, 0, 1, 0, 0, 0, 6,
+            0xff, 0xff, 0xff, 0xff, 0, 0, 0, 7, 0, 0, 0, 1, b'c', 0, 0, 0,
+        ]);
+        assert_eq!(
+            Record::deserialize_partial(&data).unwrap(),
+            (record, data.len())
+        );
+
+        for kind in [Kind::Empty, Kind::Value(8)] {
+            let mut data = Vec::new();
+            kind.serialize(&mut data);
+            assert_eq!(Kind::deserialize(&data).unwrap(), kind);
+        }
+        let pair = Pair {
+            first: "a".to_string(),
+            second: None,
+        };
+        let mut data = Vec::new();
+        pair.serialize(&mut data);
+        assert_eq!(Pair::deserialize(&data).unwrap(), pair);
+        assert_eq!(
+            decode_error::<Kind>(&[0, 0, 0, 2]),
+            (0, DecodeErrorKind::InvalidDiscriminant(2))
+        );
+    }
+
+    fn decode_error<T: Deserialize>(data: &[u8]) -> (usize, DecodeErrorKind) {
+        match T::deserialize(data) {
+            Err(Error::Xdr(err)) => (err.offset, err.kind),
+            _ => panic!("decoding must fail"),
+        }
+    }
+
+    #[test]
+    fn decode_errors() {
+        let unexpected_end =
+            |needed, available| DecodeErrorKind::UnexpectedEnd { needed, available };
+        assert_eq!(decode_error::<u64>(&[0; 7]), (0, unexpected_end(8, 7)));
+        assert_eq!(
+            decode_error::<Vec<bool>>(&[0, 0, 0, 1, 0, 0, 0, 2]),
+            (4, DecodeErrorKind::InvalidBool(2))
+        );
+        // missing padding
+        assert_eq!(
+            decode_error::<Vec<u8>>(&[0, 0, 0, 2, 1, 2, 0]),
+            (6, unexpected_end(2, 1))
+        );
+        assert_eq!(
+            decode_error::<Vec<u8>>(&[0xff, 0xff, 0xff, 0xff, 1]),
+            (4, unexpected_end(u32::MAX as usize, 1))
+        );
+        assert_eq!(
+            decode_error::<String>(&[0, 0, 0, 1, 0xff, 0, 0, 0]),
+            (0, DecodeErrorKind::InvalidUtf8)
+        );
+        // the length of arrays is bounded by the minimum size of the elements in bytes
+        assert_eq!(
+            decode_error::<Vec<u32>>(&[0, 0, 0, 2, 0, 0, 0, 1]),
+            (0, unexpected_end(8, 4))
+        );
+        assert_eq!(
+            decode_error::<Vec<Option<u32>>>(&[0, 0, 0, 1, 0, 0, 0, 1]),
+            (8, unexpected_end(4, 0))
+        );
+        assert_eq!(
+            decode_error::<Vec<Record>>(&[0xff, 0xff, 0xff, 0xff]),
+            (0, unexpected_end(u32::MAX as usize * 40, 0))
+        );
+        // arrays of elements without data are longer than the input
+        assert_eq!(<Vec<()>>::deserialize(&[0, 0, 0, 16]).unwrap(), [(); 16]);
+    }
+}